@@ -0,0 +1,89 @@
+use crate::types::{check_length, now, Player, Result};
+use rocket::{
+    http::Status,
+    request::{self, FromRequest, Request},
+    Outcome, State,
+};
+use serde::{Deserialize, Serialize};
+
+/// The longest maintenance notice accepted, in characters
+const MAX_NOTICE_LENGTH: usize = 500;
+
+/// The token admin requests must present, admin routes are disabled without one
+pub(crate) struct AdminToken(pub(crate) Option<String>);
+
+/// Proof that a request presented the admin token as `Authorization: Bearer <token>`. Requests
+/// which did not are refused with 401 before reaching the route.
+pub(crate) struct Admin(());
+
+/// Compare without returning early so the time taken does not reveal how much of the token matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Admin {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let token = match request.guard::<State<AdminToken>>() {
+            Outcome::Success(token) => token,
+            _ => return Outcome::Failure((Status::Unauthorized, ())),
+        };
+        let presented = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "));
+        let authorized = match (&token.0, presented) {
+            (Some(token), Some(presented)) => constant_time_eq(token.as_bytes(), presented.as_bytes()),
+            _ => false,
+        };
+        if authorized {
+            Outcome::Success(Self(()))
+        } else {
+            Outcome::Failure((Status::Unauthorized, ()))
+        }
+    }
+}
+
+/// A change to a player's answer
+#[derive(Deserialize, Serialize)]
+pub(crate) struct AnswerEdit {
+    pub(crate) player: Player,
+    /// The round the answer was given in, counting from 1
+    pub(crate) round: usize,
+    /// The replacement answer, `None` to clear it
+    #[serde(default)]
+    pub(crate) answer: Option<String>,
+}
+
+/// A change to a player's name
+#[derive(Deserialize, Serialize)]
+pub(crate) struct PlayerEdit {
+    pub(crate) player: Player,
+    /// The replacement name, `None` for a placeholder
+    #[serde(default)]
+    pub(crate) name: Option<Player>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct NoticeData {
+    pub(crate) message: String,
+}
+
+/// A maintenance notice shown to every player
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub(crate) struct Notice {
+    pub(crate) message: String,
+    /// When the notice was posted in seconds since the Unix epoch, also identifying it
+    pub(crate) posted_at: u64,
+}
+
+impl Notice {
+    pub(crate) fn new(data: NoticeData) -> Result<Self> {
+        check_length(&data.message, MAX_NOTICE_LENGTH)?;
+        Ok(Self {
+            message: data.message,
+            posted_at: now(),
+        })
+    }
+}
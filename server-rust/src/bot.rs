@@ -0,0 +1,81 @@
+use crate::types::{Board, Player, Side};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// How many random candidate words a `Normal` bot compares
+const NORMAL_CANDIDATES: usize = 3;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Difficulty {
+    /// Answer with a random word from the category
+    Random,
+    /// Answer with the best of a few random words from the category
+    #[default]
+    Normal,
+    /// Answer with the word from the category which gains the most on the board
+    Greedy,
+}
+
+impl FromStr for Difficulty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(Self::Random),
+            "normal" => Ok(Self::Normal),
+            "greedy" => Ok(Self::Greedy),
+            _ => Err(format!("unknown difficulty '{}'", s)),
+        }
+    }
+}
+
+fn default_name() -> Player {
+    Player::from("Bot")
+}
+
+/// A name for a bot playing against `opponent` which differs from theirs
+pub(crate) fn name_against(opponent: &str) -> Player {
+    let name = default_name();
+    if name == opponent {
+        format!("{} 2", name)
+    } else {
+        name
+    }
+}
+
+/// A server controlled player
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub(crate) struct Bot {
+    /// The name the bot plays under
+    #[serde(default = "default_name")]
+    pub(crate) name: Player,
+    #[serde(default)]
+    pub(crate) difficulty: Difficulty,
+}
+
+impl Bot {
+    #[cfg(test)]
+    pub(crate) fn new(difficulty: Difficulty) -> Self {
+        Self {
+            name: default_name(),
+            difficulty,
+        }
+    }
+
+    /// Choose an answer for `side` from `words` given the current `board`
+    pub(crate) fn choose(&self, board: &Board, round: usize, side: Side, words: &[String]) -> String {
+        let mut rng = rand::thread_rng();
+        let candidates = match self.difficulty {
+            Difficulty::Random => return words.choose(&mut rng).cloned().unwrap_or_default(),
+            Difficulty::Normal => words.choose_multiple(&mut rng, NORMAL_CANDIDATES).collect::<Vec<_>>(),
+            Difficulty::Greedy => words.iter().collect(),
+        };
+        candidates
+            .into_iter()
+            .max_by_key(|word| board.projected_gain(round, side, word))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,371 @@
+use crate::{
+    bot::{Bot, Difficulty},
+    config::Config,
+    export::{ExportFormat, GameExport},
+    question_lookup::QuestionLookup,
+    snapshot::Snapshot,
+    types::{now, Answer, BoardDelta, CreateGameData, Game, Games, PlayerData, Rules, Side},
+    word_lookup::WordLookup,
+};
+use std::{
+    error::Error,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+use structopt::StructOpt;
+
+/// The id simulated matches are played under
+const SIMULATION_ID: &str = "simulation";
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, StructOpt)]
+pub(crate) enum Command {
+    /// Run the server. This is the default when no subcommand is given.
+    Serve,
+    /// Export a game from a running server.
+    Export {
+        /// The id of the game to export.
+        game_id: String,
+        /// The base URL of the server.
+        #[structopt(long = "server", default_value = "http://localhost:8172")]
+        server: String,
+        /// Export from the snapshot instead of a running server.
+        #[structopt(long = "storage")]
+        storage: bool,
+        /// The format to export in.
+        #[structopt(long = "format", default_value = "json", possible_values = &["json", "csv"])]
+        format: ExportFormat,
+        /// The file to write the export to instead of stdout.
+        #[structopt(long = "output", short = "o")]
+        output: Option<PathBuf>,
+    },
+    /// Import a game exported as JSON into a running server, authenticating with the configured
+    /// admin token.
+    Import {
+        /// The path to the exported game.
+        file: PathBuf,
+        /// The base URL of the server.
+        #[structopt(long = "server", default_value = "http://localhost:8172")]
+        server: String,
+        /// Import into the snapshot instead of a running server.
+        #[structopt(long = "storage")]
+        storage: bool,
+    },
+    /// Check a question pack for blank, overlong and duplicate questions. Defaults to the configured
+    /// questions file.
+    ValidateQuestions {
+        /// The question pack to check.
+        file: Option<PathBuf>,
+    },
+    /// Inspect and maintain the snapshot games are stored in. The server overwrites the snapshot
+    /// when it shuts down so change it only while the server is stopped.
+    Storage(StorageCommand),
+    /// Play a match between two bots with the configured rules, questions and words and print it.
+    Simulate {
+        /// How player one's bot chooses answers.
+        #[structopt(long = "player-one", default_value = "normal", possible_values = &["random", "normal", "greedy"])]
+        player_one: Difficulty,
+        /// How player two's bot chooses answers.
+        #[structopt(long = "player-two", default_value = "normal", possible_values = &["random", "normal", "greedy"])]
+        player_two: Difficulty,
+        /// Stop the match after this many rounds if nobody has won.
+        #[structopt(long = "max-rounds", default_value = "100")]
+        max_rounds: usize,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) enum StorageCommand {
+    /// List every stored game.
+    List,
+    /// Show a stored game round by round.
+    Inspect {
+        /// The id of the game to show.
+        game_id: String,
+    },
+    /// Remove old finished games.
+    Compact {
+        /// Remove games created more than this many days ago.
+        #[structopt(long = "older-than", default_value = "30")]
+        older_than: u64,
+        /// Also remove unfinished games.
+        #[structopt(long = "unfinished")]
+        unfinished: bool,
+    },
+}
+
+fn api_url(server: &str, path: &str) -> String {
+    format!("{}/api/v1/{}", server.trim_end_matches('/'), path)
+}
+
+/// Turn an error response from the server into an error
+fn check(response: reqwest::blocking::Response) -> Result<reqwest::blocking::Response, Box<dyn Error>> {
+    if response.status().is_success() {
+        Ok(response)
+    } else {
+        Err(format!("server responded with {}: {}", response.status(), response.text()?).into())
+    }
+}
+
+/// The configured snapshot path
+fn snapshot_path(config: &Config) -> Result<&Path, Box<dyn Error>> {
+    config
+        .storage
+        .snapshot
+        .as_deref()
+        .ok_or_else(|| "no snapshot is configured, pass --snapshot or set storage.snapshot".into())
+}
+
+/// The configured snapshot, which must exist
+fn read_snapshot(config: &Config) -> Result<Snapshot, Box<dyn Error>> {
+    let path = snapshot_path(config)?;
+    Snapshot::read(path)?.ok_or_else(|| format!("snapshot {:?} does not exist", path).into())
+}
+
+fn load_questions(config: &Config) -> Result<QuestionLookup, Box<dyn Error>> {
+    let mut questions = QuestionLookup::default();
+    if let Some(file) = &config.questions.file {
+        questions.populate_from_file(file)?;
+    }
+    Ok(questions)
+}
+
+fn load_words(config: &Config) -> Result<WordLookup, Box<dyn Error>> {
+    let file = match &config.questions.words_file {
+        Some(file) => file,
+        None => return Ok(WordLookup::bundled()),
+    };
+    let mut words = WordLookup::default();
+    words.populate_from_file(file)?;
+    Ok(words)
+}
+
+fn player(game: &Game, side: Side) -> &str {
+    let player = match side {
+        Side::PlayerOne => &game.player_one,
+        Side::PlayerTwo => &game.player_two,
+    };
+    player.as_deref().unwrap_or("-")
+}
+
+/// Where a game has got to in a few words
+fn status(game: &Game) -> String {
+    if game.finished {
+        match &game.winner {
+            Some(winner) => format!("{} won", winner),
+            None => String::from("draw"),
+        }
+    } else if game.player_two.is_none() {
+        String::from("waiting for a player")
+    } else {
+        format!("round {}", game.rounds.len())
+    }
+}
+
+/// A game round by round
+pub(crate) fn describe(game_id: &str, game: &Game) -> String {
+    let mut out = String::new();
+    let rules = game.board.rules.summary();
+    let rules = if rules.is_empty() { String::from("default") } else { rules.join(", ") };
+    let _ = writeln!(out, "game {} (version {})", game_id, game.version);
+    let _ = writeln!(out, "players: {} vs {}", player(game, Side::PlayerOne), player(game, Side::PlayerTwo));
+    let _ = writeln!(out, "rules: {}", rules);
+    let _ = writeln!(out, "status: {}", status(game));
+    for (index, round) in game.rounds.iter().enumerate() {
+        let _ = writeln!(out, "round {}", index + 1);
+        for side in [Side::PlayerOne, Side::PlayerTwo] {
+            let answer = round.answer(side).map_or_else(|| String::from("-"), |answer| format!("{:?}", answer));
+            let _ = writeln!(out, "  {}: {:?} -> {}", player(game, side), round.question(side), answer);
+        }
+        for delta in &round.deltas {
+            let change = match delta {
+                BoardDelta::Moved { .. } => continue,
+                BoardDelta::Captured { letter, side } => format!("{} captured {}", player(game, *side), letter),
+                BoardDelta::Stolen { letter, side } => format!("{} stole {}", player(game, *side), letter),
+                BoardDelta::Sacrificed { letter, side, saved } => {
+                    format!("{} sacrificed {} to save {}", player(game, *side), letter, saved)
+                }
+            };
+            let _ = writeln!(out, "  {}", change);
+        }
+    }
+    out
+}
+
+/// Play a match between two bots until one wins or `max_rounds` rounds have been played
+pub(crate) fn simulate(
+    rules: Rules,
+    bots: [Bot; 2],
+    questions: &QuestionLookup,
+    words: &WordLookup,
+    max_rounds: usize,
+) -> Result<Game, Box<dyn Error>> {
+    let games = Games::default();
+    let [player_one, player_two] = bots;
+    let data = CreateGameData {
+        player: player_one.name.clone(),
+        profile: None,
+        rules: Some(rules),
+        public: false,
+        best_of: 1,
+        family_mode: None,
+    };
+    games.create(SIMULATION_ID.to_string(), data, questions.get(), questions.get())?;
+    let game = games.get(SIMULATION_ID)?;
+    let mut game = game.lock();
+    let data = PlayerData {
+        player: player_two.name.clone(),
+        profile: None,
+    };
+    games.join(SIMULATION_ID, &mut game, data)?;
+    while !game.finished && game.completed_rounds() < max_rounds {
+        for (bot, side) in [(&player_one, Side::PlayerOne), (&player_two, Side::PlayerTwo)] {
+            let candidates = words.get(game.current_round().question(side));
+            let answer = bot.choose(&game.board, game.rounds.len(), side, candidates);
+            game.answer(Answer::new(&bot.name, &answer), questions)?;
+        }
+    }
+    Ok(game.clone())
+}
+
+impl Command {
+    pub(crate) fn run(self, config: &Config) -> Result<(), Box<dyn Error>> {
+        let client = reqwest::blocking::Client::new();
+        match self {
+            Self::Serve => unreachable!("serve is handled by main"),
+            Self::Export {
+                game_id,
+                server,
+                storage,
+                format,
+                output,
+            } => {
+                let body = if storage {
+                    let snapshot = read_snapshot(config)?;
+                    let export = snapshot.game(&game_id).ok_or_else(|| format!("no game {:?} is stored", game_id))?;
+                    match format {
+                        ExportFormat::Json => export.to_json(),
+                        ExportFormat::Csv => export.to_csv(),
+                    }
+                } else {
+                    let format = match format {
+                        ExportFormat::Json => "json",
+                        ExportFormat::Csv => "csv",
+                    };
+                    let url = api_url(&server, &format!("game/{}/export?format={}", game_id, format));
+                    check(client.get(url).send()?)?.text()?
+                };
+                match output {
+                    Some(output) => fs::write(output, body)?,
+                    None => print!("{}", body),
+                }
+            }
+            Self::Import { file, server, storage } => {
+                let body = fs::read_to_string(file)?;
+                if storage {
+                    let path = snapshot_path(config)?;
+                    let export = serde_json::from_str::<GameExport>(&body)?;
+                    export.validate(&config.input_limits)?;
+                    let mut snapshot = Snapshot::read(path)?.unwrap_or_else(|| Snapshot::new(Vec::new(), Vec::new()));
+                    if snapshot.game(&export.game_id).is_some() {
+                        return Err(format!("game {:?} is already stored", export.game_id).into());
+                    }
+                    snapshot.games.push(export);
+                    snapshot.write(path)?;
+                } else {
+                    let token = config
+                        .admin
+                        .token
+                        .as_deref()
+                        .ok_or("importing into a server needs its admin token, set admin.token")?;
+                    check(
+                        client
+                            .post(api_url(&server, "import"))
+                            .header(reqwest::header::CONTENT_TYPE, "application/json")
+                            .bearer_auth(token)
+                            .body(body)
+                            .send()?,
+                    )?;
+                }
+            }
+            Self::ValidateQuestions { file } => {
+                let file = file
+                    .or_else(|| config.questions.file.clone())
+                    .ok_or("no question pack given, pass one or set questions.file")?;
+                let mut questions = QuestionLookup::default();
+                questions.populate_from_file(&file)?;
+                let problems = questions.problems();
+                for problem in &problems {
+                    println!("{}", problem);
+                }
+                let words = load_words(config)?;
+                let uncovered = questions.iter().filter(|question| !words.has_category(question)).count();
+                if uncovered > 0 {
+                    println!("note: bots answer {} questions from every word as they have no category", uncovered);
+                }
+                println!("{} questions, {} problems", questions.len(), problems.len());
+                if !problems.is_empty() {
+                    return Err(format!("{:?} has problems", file).into());
+                }
+            }
+            Self::Storage(command) => command.run(config)?,
+            Self::Simulate {
+                player_one,
+                player_two,
+                max_rounds,
+            } => {
+                let bots = [
+                    Bot {
+                        name: String::from("Bot One"),
+                        difficulty: player_one,
+                    },
+                    Bot {
+                        name: String::from("Bot Two"),
+                        difficulty: player_two,
+                    },
+                ];
+                let questions = load_questions(config)?;
+                let words = load_words(config)?;
+                let game = simulate(config.game.rules.clone(), bots, &questions, &words, max_rounds)?;
+                print!("{}", describe(SIMULATION_ID, &game));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StorageCommand {
+    fn run(self, config: &Config) -> Result<(), Box<dyn Error>> {
+        match self {
+            Self::List => {
+                let mut snapshot = read_snapshot(config)?;
+                snapshot.games.sort_by(|a, b| a.game_id.cmp(&b.game_id));
+                for export in &snapshot.games {
+                    let game = &export.game;
+                    println!(
+                        "{}\t{} vs {}\t{}",
+                        export.game_id,
+                        player(game, Side::PlayerOne),
+                        player(game, Side::PlayerTwo),
+                        status(game)
+                    );
+                }
+                println!("{} games, {} profiles", snapshot.games.len(), snapshot.profiles.len());
+            }
+            Self::Inspect { game_id } => {
+                let snapshot = read_snapshot(config)?;
+                let export = snapshot.game(&game_id).ok_or_else(|| format!("no game {:?} is stored", game_id))?;
+                print!("{}", describe(&export.game_id, &export.game));
+            }
+            Self::Compact { older_than, unfinished } => {
+                let mut snapshot = read_snapshot(config)?;
+                let cutoff = now().saturating_sub(older_than.saturating_mul(SECONDS_PER_DAY));
+                let removed = snapshot.compact(cutoff, unfinished);
+                snapshot.write(snapshot_path(config)?)?;
+                println!("removed {} games, {} remain", removed, snapshot.games.len());
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,449 @@
+use crate::{filter::FilterMode, logging::LogFormat, rate_limit::Limits, types::Rules, validation::InputLimits};
+use rocket::{
+    config::{Environment, LoggingLevel},
+    http::Method,
+};
+use rocket_cors::{AllowedOrigins, CorsOptions};
+use serde::{Deserialize, Serialize};
+use std::{
+    env, error, fmt, fs, io,
+    path::{Path, PathBuf},
+};
+use toml::Value;
+
+/// The prefix of environment variables overriding the configuration
+const ENV_PREFIX: &str = "WEIGHTY_";
+/// The environment variable naming the configuration file when `--config` is not given
+const CONFIG_ENV: &str = "WEIGHTY_CONFIG";
+/// The environment variable read when building to record the commit, which deploys may leave set
+const BUILD_COMMIT_ENV: &str = "WEIGHTY_BUILD_COMMIT";
+/// How long a player waits in the matchmaking queue by default, in seconds
+const DEFAULT_MATCHMAKING_TIMEOUT: u64 = 30;
+/// How long a long-polling request waits for the game to change by default, in seconds
+const DEFAULT_LONG_POLL_TIMEOUT: u64 = 25;
+/// The longest a long-polling request may wait, in seconds. Clients poll again once the wait ends
+/// so longer waits only tie up workers for longer.
+const MAX_LONG_POLL_TIMEOUT: u64 = 60;
+
+#[derive(Debug)]
+pub(crate) enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(String, String),
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(path, e) => write!(f, "failed to read config file {:?}, err: {}", path, e),
+            Self::Parse(source, e) => write!(f, "failed to parse config from {}, err: {}", source, e),
+            Self::Invalid(problems) => {
+                write!(f, "invalid configuration:")?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl error::Error for ConfigError {}
+
+/// The server configuration, layered from defaults, a TOML file, `WEIGHTY_*` environment
+/// variables and command line flags, each overriding the last
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    pub(crate) server: ServerConfig,
+    pub(crate) storage: StorageConfig,
+    pub(crate) game: GameConfig,
+    pub(crate) questions: QuestionsConfig,
+    pub(crate) rate_limits: Limits,
+    pub(crate) input_limits: InputLimits,
+    pub(crate) cors: CorsConfig,
+    pub(crate) admin: AdminConfig,
+    pub(crate) filter: FilterConfig,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ServerConfig {
+    /// An IP address or host the application will listen on
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    /// The Rocket environment, one of `development`, `staging` or `production`
+    pub(crate) environment: String,
+    /// One of `off`, `debug`, `normal` or `critical`
+    pub(crate) log_level: String,
+    /// Log lines as `text` or `json`
+    pub(crate) log_format: LogFormat,
+    /// The base URL the server and client are publicly reachable at, used to build join URLs
+    pub(crate) public_url: String,
+    /// How long a `?since=` request waits for the game to change before responding, in seconds
+    pub(crate) long_poll_timeout: u64,
+    /// The number of requests handled at once, twice the number of cores if not given
+    pub(crate) workers: Option<u16>,
+    /// How many `?since=` requests may wait at once, each holding a worker, half the workers if
+    /// not given. Further requests respond straight away.
+    pub(crate) max_long_polls: Option<usize>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: String::from("0.0.0.0"),
+            port: 8172,
+            environment: String::from("staging"),
+            log_level: String::from("normal"),
+            log_format: LogFormat::Text,
+            public_url: String::new(),
+            long_poll_timeout: DEFAULT_LONG_POLL_TIMEOUT,
+            workers: None,
+            max_long_polls: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub(crate) fn environment(&self) -> Environment {
+        self.environment.parse().unwrap_or(Environment::Staging)
+    }
+
+    pub(crate) fn log_level(&self) -> LoggingLevel {
+        self.log_level.parse().unwrap_or(LoggingLevel::Normal)
+    }
+
+    pub(crate) fn workers(&self) -> u16 {
+        self.workers.unwrap_or_else(|| rocket::Config::new(self.environment()).workers)
+    }
+
+    pub(crate) fn max_long_polls(&self) -> usize {
+        self.max_long_polls.unwrap_or(usize::from(self.workers()) / 2)
+    }
+}
+
+/// Where games are kept
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Backend {
+    /// In memory, lost when the server stops
+    #[default]
+    Memory,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct StorageConfig {
+    pub(crate) backend: Backend,
+    /// Where games are saved on shutdown and restored from on start
+    pub(crate) snapshot: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct GameConfig {
+    /// The rules for games created without any
+    pub(crate) rules: Rules,
+    /// How long a player waits in the matchmaking queue before their fallback is used, in seconds
+    pub(crate) matchmaking_timeout: u64,
+    /// Scale rating changes by the margin of captured letters
+    pub(crate) rating_margin: bool,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            rules: Rules::default(),
+            matchmaking_timeout: DEFAULT_MATCHMAKING_TIMEOUT,
+            rating_margin: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct QuestionsConfig {
+    /// A file containing newline delimited questions
+    pub(crate) file: Option<PathBuf>,
+    /// A file of candidate answers for bots, one `Category: word, word` per line, the bundled
+    /// `words.txt` if not given
+    pub(crate) words_file: Option<PathBuf>,
+}
+
+/// The shortest admin token accepted
+const MIN_ADMIN_TOKEN_LENGTH: usize = 16;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct AdminConfig {
+    /// The bearer token admin requests must present, the admin API is disabled without one
+    pub(crate) token: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct FilterConfig {
+    /// A file of blocked words, one per line, ending in `*` to block every word starting with it
+    pub(crate) words_file: Option<PathBuf>,
+    /// What happens to names and answers containing a blocked word
+    pub(crate) mode: FilterMode,
+    /// Whether games are in family mode unless created otherwise
+    pub(crate) family_mode: bool,
+}
+
+/// Which cross-origin requests are allowed, by default none
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct CorsConfig {
+    /// The exact origins allowed, `*` allowing any
+    pub(crate) allowed_origins: Vec<String>,
+    pub(crate) allowed_methods: Vec<String>,
+    pub(crate) allow_credentials: bool,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: ["GET", "POST", "PUT", "DELETE"].iter().map(ToString::to_string).collect(),
+            allow_credentials: false,
+        }
+    }
+}
+
+impl CorsConfig {
+    pub(crate) fn to_cors(&self) -> Result<rocket_cors::Cors, String> {
+        let origins = if self.allowed_origins.iter().any(|origin| origin == "*") {
+            AllowedOrigins::all()
+        } else {
+            AllowedOrigins::some_exact(&self.allowed_origins)
+        };
+        let methods = self
+            .allowed_methods
+            .iter()
+            .map(|method| {
+                method
+                    .parse::<Method>()
+                    .map(From::from)
+                    .map_err(|_| format!("unknown method {:?}", method))
+            })
+            .collect::<Result<_, _>>()?;
+        CorsOptions::default()
+            .allowed_origins(origins)
+            .allowed_methods(methods)
+            .expose_headers(["ETag", "Retry-After", "X-Request-Id", "X-Shutting-Down", "X-Notice"].iter().map(ToString::to_string).collect())
+            .allow_credentials(self.allow_credentials)
+            .to_cors()
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl Config {
+    /// The defaults overridden by the config file at `path`, or `$WEIGHTY_CONFIG`, and then by
+    /// `WEIGHTY_*` environment variables
+    pub(crate) fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let path = path.map(PathBuf::from).or_else(|| env::var_os(CONFIG_ENV).map(PathBuf::from));
+        let mut value = Value::try_from(Self::default()).expect("to serialize the default config");
+        if let Some(path) = path {
+            let contents = fs::read_to_string(&path).map_err(|e| ConfigError::Read(path.clone(), e))?;
+            let file = contents
+                .parse::<Value>()
+                .map_err(|e| ConfigError::Parse(format!("{:?}", path), e.to_string()))?;
+            merge(&mut value, file);
+            // Check the file on its own so its errors are not blamed on the environment
+            value
+                .clone()
+                .try_into::<Self>()
+                .map_err(|e| ConfigError::Parse(format!("{:?}", path), e.to_string()))?;
+        }
+        let mut problems = Vec::new();
+        // Other programs' variables need not be UTF-8 so only ours are decoded
+        for (name, raw) in env::vars_os() {
+            let name = match name.to_str() {
+                Some(name) if name.starts_with(ENV_PREFIX) => name,
+                _ => continue,
+            };
+            if name == CONFIG_ENV || name == BUILD_COMMIT_ENV {
+                continue;
+            }
+            let key = &name[ENV_PREFIX.len()..];
+            match raw.to_str() {
+                Some(raw) if set_env(&mut value, &key.to_lowercase(), raw) => (),
+                Some(_) => problems.push(format!("{}: no such setting", name)),
+                None => problems.push(format!("{}: not valid UTF-8", name)),
+            }
+        }
+        if !problems.is_empty() {
+            return Err(ConfigError::Invalid(problems));
+        }
+        value
+            .try_into()
+            .map_err(|e| ConfigError::Parse(String::from("the environment"), e.to_string()))
+    }
+
+    /// Check the settings make sense together, listing every problem found
+    pub(crate) fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+        if self.server.host.is_empty() {
+            problems.push(String::from("server.host: must not be empty"));
+        }
+        if self.server.port == 0 {
+            problems.push(String::from("server.port: must not be 0"));
+        }
+        if self.server.environment.parse::<Environment>().is_err() {
+            problems.push(format!(
+                "server.environment: {:?} is not one of development, staging or production",
+                self.server.environment
+            ));
+        }
+        if self.server.log_level.parse::<LoggingLevel>().is_err() {
+            problems.push(format!(
+                "server.log_level: {:?} is not one of off, debug, normal or critical",
+                self.server.log_level
+            ));
+        }
+        if self.server.workers == Some(0) {
+            problems.push(String::from("server.workers: must not be 0"));
+        }
+        if self.server.long_poll_timeout > MAX_LONG_POLL_TIMEOUT {
+            problems.push(format!("server.long_poll_timeout: must be at most {} seconds", MAX_LONG_POLL_TIMEOUT));
+        }
+        if self.server.max_long_polls() >= usize::from(self.server.workers()) {
+            problems.push(format!(
+                "server.max_long_polls: must be less than the {} workers so other requests are still handled",
+                self.server.workers()
+            ));
+        }
+        let public_url = &self.server.public_url;
+        if !public_url.is_empty() && !public_url.starts_with("http://") && !public_url.starts_with("https://") {
+            problems.push(format!("server.public_url: {:?} is not an http or https URL", public_url));
+        }
+        if self.game.rules.validate().is_err() {
+            problems.push(String::from("game.rules: the rules are invalid"));
+        }
+        for (key, path) in [
+            ("questions.file", &self.questions.file),
+            ("questions.words_file", &self.questions.words_file),
+            ("filter.words_file", &self.filter.words_file),
+        ] {
+            if let Some(path) = path {
+                if !path.is_file() {
+                    problems.push(format!("{}: {:?} is not a file", key, path));
+                }
+            }
+        }
+        if let Some(snapshot) = &self.storage.snapshot {
+            if snapshot.is_dir() {
+                problems.push(format!("storage.snapshot: {:?} is a directory", snapshot));
+            }
+        }
+        let limits = &self.rate_limits;
+        if limits.window == 0 && (limits.per_ip > 0 || limits.per_game > 0) {
+            problems.push(String::from("rate_limits.window: must not be 0 while a limit is set"));
+        }
+        for (key, limit) in [
+            ("input_limits.player_name", self.input_limits.player_name),
+            ("input_limits.answer", self.input_limits.answer),
+            ("input_limits.game_id", self.input_limits.game_id),
+        ] {
+            if limit == 0 {
+                problems.push(format!("{}: must not be 0", key));
+            }
+        }
+        if let Some(token) = &self.admin.token {
+            if token.len() < MIN_ADMIN_TOKEN_LENGTH {
+                problems.push(format!("admin.token: must be at least {} characters", MIN_ADMIN_TOKEN_LENGTH));
+            }
+        }
+        if let Err(e) = self.cors.to_cors() {
+            problems.push(format!("cors: {}", e));
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+
+    /// The configuration as TOML, with the admin token hidden
+    pub(crate) fn to_toml(&self) -> String {
+        let mut config = self.clone();
+        if config.admin.token.is_some() {
+            config.admin.token = Some(String::from("<redacted>"));
+        }
+        let value = Value::try_from(config).expect("to serialize the config");
+        toml::to_string_pretty(&value).expect("to format the config")
+    }
+}
+
+/// Recursively overwrite `base` with the settings in `overrides`
+fn merge(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (Value::Table(base), Value::Table(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+/// Set the setting named by `key`, like `server_port` or `game_rules_max_hints`, from an
+/// environment variable, returning whether there is such a setting
+fn set_env(table: &mut Value, key: &str, raw: &str) -> bool {
+    let table = match table {
+        Value::Table(table) => table,
+        _ => return false,
+    };
+    // Descend into the nested table the key starts with, if any
+    let nested = table
+        .iter()
+        .filter(|(_, value)| value.is_table())
+        .map(|(name, _)| name.clone())
+        .find(|name| key.starts_with(&format!("{}_", name)));
+    if let Some(name) = nested {
+        let rest = &key[name.len() + 1..];
+        return set_env(table.get_mut(&name).expect("table to exist"), rest, raw);
+    }
+    let value = match table.get(key) {
+        Some(Value::String(_)) => Value::String(raw.to_string()),
+        Some(Value::Array(_)) if !raw.trim_start().starts_with('[') => Value::Array(
+            raw.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| Value::String(item.to_string()))
+                .collect(),
+        ),
+        // Unset optional settings are missing from the table so they must be known by name
+        None if !OPTIONAL_SETTINGS.contains(&key) => return false,
+        _ => parse_value(raw),
+    };
+    table.insert(key.to_string(), value);
+    true
+}
+
+/// Settings which are `None` by default and so cannot be discovered from the defaults
+const OPTIONAL_SETTINGS: &[&str] = &[
+    "file",
+    "words_file",
+    "snapshot",
+    "locked_letter",
+    "token",
+    "workers",
+    "max_long_polls",
+];
+
+/// A TOML value, or a string if `raw` is not valid TOML
+fn parse_value(raw: &str) -> Value {
+    format!("value = {}", raw)
+        .parse::<Value>()
+        .ok()
+        .and_then(|value| value.get("value").cloned())
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
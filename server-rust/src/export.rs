@@ -0,0 +1,134 @@
+use crate::{
+    types::{now, BoardDelta, Error, Game, Result, Side},
+    validation::InputLimits,
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Write, str::FromStr};
+
+/// The version of the export format, bumped whenever `GameExport` changes incompatibly
+pub(crate) const SCHEMA_VERSION: u32 = 1;
+
+const CSV_HEADER: &str = "round,player_one,player_one_question,player_one_answer,player_two,\
+player_two_question,player_two_answer,moves,player_one_captured,player_two_captured,sacrificed";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ExportFormat {
+    /// The full game as versioned JSON which can be imported again
+    Json,
+    /// One line per round
+    Csv,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            _ => Err(format!("unknown export format '{}'", s)),
+        }
+    }
+}
+
+/// An archived game
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct GameExport {
+    pub(crate) schema_version: u32,
+    pub(crate) game_id: String,
+    /// When the game was exported in seconds since the Unix epoch
+    pub(crate) exported_at: u64,
+    pub(crate) game: Game,
+}
+
+impl GameExport {
+    pub(crate) fn new(game_id: &str, game: &Game) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            game_id: game_id.to_string(),
+            exported_at: now(),
+            game: game.clone(),
+        }
+    }
+
+    /// Check the export can be restored by this version of the server and its text is within
+    /// `limits`
+    pub(crate) fn validate(&self, limits: &InputLimits) -> Result<()> {
+        if self.schema_version != SCHEMA_VERSION {
+            return Err(Error::UnsupportedSchemaVersion);
+        }
+        let game = &self.game;
+        if game.rounds.is_empty() {
+            return Err(Error::InvalidExport);
+        }
+        limits.game_id(&self.game_id)?;
+        for player in game.player_one.iter().chain(&game.player_two) {
+            limits.player_name(player)?;
+        }
+        for round in &game.rounds {
+            for answer in round.player_one_answer.iter().chain(&round.player_two_answer) {
+                limits.answer(answer)?;
+            }
+        }
+        game.board.validate()?;
+        if let Some(winner) = &game.winner {
+            if !game.finished || game.side(winner).is_none() {
+                return Err(Error::InvalidExport);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("to GameExport serialize")
+    }
+
+    pub(crate) fn to_csv(&self) -> String {
+        let game = &self.game;
+        let player_one = game.player_one.clone().unwrap_or_default();
+        let player_two = game.player_two.clone().unwrap_or_default();
+        let mut csv = String::from(CSV_HEADER);
+        csv.push('\n');
+        for (index, round) in game.rounds.iter().enumerate() {
+            let mut moves = Vec::new();
+            let mut player_one_captured = String::new();
+            let mut player_two_captured = String::new();
+            let mut sacrificed = String::new();
+            for delta in &round.deltas {
+                match delta {
+                    BoardDelta::Moved { letter, from, to } => moves.push(format!("{}{:+}", letter, to - from)),
+                    BoardDelta::Captured { letter, side } | BoardDelta::Stolen { letter, side } => match side {
+                        Side::PlayerOne => player_one_captured.push(*letter),
+                        Side::PlayerTwo => player_two_captured.push(*letter),
+                    },
+                    BoardDelta::Sacrificed { letter, .. } => sacrificed.push(*letter),
+                }
+            }
+            let fields = [
+                (index + 1).to_string(),
+                player_one.clone(),
+                round.question(Side::PlayerOne).to_string(),
+                round.answer(Side::PlayerOne).cloned().unwrap_or_default(),
+                player_two.clone(),
+                round.question(Side::PlayerTwo).to_string(),
+                round.answer(Side::PlayerTwo).cloned().unwrap_or_default(),
+                moves.join(" "),
+                player_one_captured,
+                player_two_captured,
+                sacrificed,
+            ];
+            let line = fields.iter().map(|field| escape(field)).collect::<Vec<_>>().join(",");
+            writeln!(csv, "{}", line).expect("to write to String");
+        }
+        csv
+    }
+}
+
+/// Quote a CSV field if it contains a delimiter, quote or newline
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
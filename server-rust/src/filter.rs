@@ -0,0 +1,169 @@
+use crate::types::Player;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    fmt,
+    path::Path,
+};
+
+/// What happens to names and answers in family mode games which contain a blocked word
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum FilterMode {
+    /// Refuse them
+    Reject,
+    /// Replace each blocked word with asterisks. Names are rejected instead as players are
+    /// identified by them.
+    #[default]
+    Mask,
+    /// Accept them unchanged but record them for moderators to review
+    Flag,
+}
+
+/// Whether filtered text was a player's name or an answer
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Field {
+    Name,
+    Answer,
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Name => write!(f, "name"),
+            Self::Answer => write!(f, "answer"),
+        }
+    }
+}
+
+/// Text containing a blocked word which was let through for review
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub(crate) struct Flag {
+    /// The game the text was sent to, `None` for the matchmaking queue
+    pub(crate) game_id: Option<String>,
+    pub(crate) player: Player,
+    pub(crate) field: Field,
+    pub(crate) text: String,
+    /// When the text was sent in seconds since the Unix epoch
+    pub(crate) at: u64,
+}
+
+/// Read digits and symbols commonly swapped for letters as those letters
+fn unleet(c: char) -> char {
+    match c {
+        '0' => 'o',
+        '1' | '!' | '|' => 'i',
+        '3' => 'e',
+        '4' | '@' => 'a',
+        '5' | '$' => 's',
+        '7' | '+' => 't',
+        '8' => 'b',
+        '9' => 'g',
+        c => c,
+    }
+}
+
+/// Reduce a word to the letters it reads as so disguised spellings compare equal, for example
+/// `B@d_W0rd` to `badword`
+fn normalize(word: &str) -> String {
+    word.chars()
+        .flat_map(char::to_lowercase)
+        .map(unleet)
+        .filter(|c| c.is_alphabetic())
+        .collect()
+}
+
+/// Shorten runs of the same letter to at most `max`, undoing letters stretched to dodge filters
+fn collapse(word: &str, max: usize) -> String {
+    let mut collapsed = String::new();
+    let mut run = 0;
+    let mut previous = None;
+    for c in word.chars() {
+        run = if previous == Some(c) { run + 1 } else { 1 };
+        previous = Some(c);
+        if run <= max {
+            collapsed.push(c);
+        }
+    }
+    collapsed
+}
+
+/// Blocked words matched against each whitespace separated word of a name or answer
+#[derive(Default)]
+pub(crate) struct ContentFilter {
+    words: HashSet<String>,
+    /// Entries ending in `*`, which block any word starting with them
+    prefixes: Vec<String>,
+    pub(crate) mode: FilterMode,
+}
+
+impl ContentFilter {
+    /// Populate from a file with one blocked word per line, ignoring blank lines and `#` comments
+    pub(crate) fn populate_from_file(&mut self, path: &Path) -> io::Result<()> {
+        let file = File::open(path)?;
+        let reader = BufReader::new(file);
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                self.insert(line);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn insert(&mut self, word: &str) {
+        let (word, prefix) = match word.strip_suffix('*') {
+            Some(word) => (normalize(word), true),
+            None => (normalize(word), false),
+        };
+        // An empty entry would block everything
+        if word.is_empty() {
+            return;
+        }
+        if prefix {
+            self.prefixes.push(word);
+        } else {
+            self.words.insert(word);
+        }
+    }
+
+    fn blocks(&self, word: &str) -> bool {
+        let word = normalize(word);
+        // Blocked words rarely repeat a letter more than twice so compare the stretched word
+        // shortened both ways
+        [word.clone(), collapse(&word, 1), collapse(&word, 2)].iter().any(|word| {
+            !word.is_empty()
+                && (self.words.contains(word) || self.prefixes.iter().any(|prefix| word.starts_with(prefix.as_str())))
+        })
+    }
+
+    /// `text` with every blocked word replaced by asterisks, `None` if it contains none
+    pub(crate) fn mask(&self, text: &str) -> Option<String> {
+        let mut blocked = false;
+        let mut masked = String::with_capacity(text.len());
+        let mut rest = text;
+        while !rest.is_empty() {
+            let split = rest.find(|c: char| !c.is_whitespace()).unwrap_or(rest.len());
+            let (space, after) = rest.split_at(split);
+            masked.push_str(space);
+            let split = after.find(char::is_whitespace).unwrap_or(after.len());
+            let (word, after) = after.split_at(split);
+            if self.blocks(word) {
+                blocked = true;
+                masked.extend(word.chars().map(|_| '*'));
+            } else {
+                masked.push_str(word);
+            }
+            rest = after;
+        }
+        if blocked {
+            Some(masked)
+        } else {
+            None
+        }
+    }
+}
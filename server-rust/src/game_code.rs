@@ -0,0 +1,80 @@
+use rand::{seq::SliceRandom, Rng};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::uri::Origin,
+    Data, Request,
+};
+
+/// Consonants which cannot be mistaken for a digit or another letter
+const CONSONANTS: &[u8] = b"BCDFGHJKMNPRSTVWXZ";
+/// Vowels which cannot be mistaken for a digit (`O` and `0`, `I` and `1`)
+const VOWELS: &[u8] = b"AEU";
+/// The number of consonant-vowel syllables in a code
+const SYLLABLES: usize = 3;
+
+/// Generate a short pronounceable code such as `BAKEMU`
+pub(crate) fn generate<R: Rng>(rng: &mut R) -> String {
+    let mut code = String::with_capacity(SYLLABLES * 2);
+    for _ in 0..SYLLABLES {
+        code.push(char::from(*CONSONANTS.choose(rng).expect("consonants to be non-empty")));
+        code.push(char::from(*VOWELS.choose(rng).expect("vowels to be non-empty")));
+    }
+    code
+}
+
+/// Whether `code` could have been produced by `generate`
+pub(crate) fn is_valid(code: &str) -> bool {
+    code.len() == SYLLABLES * 2
+        && code.bytes().enumerate().all(|(i, c)| {
+            if i % 2 == 0 {
+                CONSONANTS.contains(&c)
+            } else {
+                VOWELS.contains(&c)
+            }
+        })
+}
+
+/// `game_id` in upper case if it is a code typed in any case, otherwise unchanged
+pub(crate) fn normalize(game_id: &str) -> String {
+    let upper = game_id.to_ascii_uppercase();
+    if is_valid(&upper) {
+        upper
+    } else {
+        game_id.to_string()
+    }
+}
+
+/// Rewrites codes typed in lower or mixed case in game paths to the generated upper case, so every
+/// route, log and limit sees one id per game
+pub(crate) struct CaseInsensitiveCodes;
+
+impl Fairing for CaseInsensitiveCodes {
+    fn info(&self) -> Info {
+        Info {
+            name: "Case Insensitive Codes",
+            kind: Kind::Request,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        let uri = request.uri();
+        let mut segments = uri.path().split('/').map(String::from).collect::<Vec<_>>();
+        let index = match segments.iter().position(|segment| segment == "game") {
+            Some(index) if index + 1 < segments.len() => index + 1,
+            _ => return,
+        };
+        let game_id = normalize(&segments[index]);
+        if game_id == segments[index] {
+            return;
+        }
+        segments[index] = game_id;
+        let mut rewritten = segments.join("/");
+        if let Some(query) = uri.query() {
+            rewritten.push('?');
+            rewritten.push_str(query);
+        }
+        if let Ok(rewritten) = Origin::parse_owned(rewritten) {
+            request.set_uri(rewritten);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use crate::{config::Backend, question_lookup::QuestionLookup, types::Games};
+use rocket::http::Status;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// How long the storage check waits for the games before reporting them unavailable
+const STORAGE_TIMEOUT: Duration = Duration::from_secs(1);
+
+const READY: &str = "ready";
+
+/// What the server was started with, used to judge whether it is ready
+pub(crate) struct Health {
+    started: Instant,
+    backend: Backend,
+    /// Whether a question file was configured, in which case an empty bank means it failed to load
+    questions_required: bool,
+}
+
+impl Health {
+    pub(crate) fn new(backend: Backend, questions_required: bool) -> Self {
+        Self {
+            started: Instant::now(),
+            backend,
+            questions_required,
+        }
+    }
+
+    pub(crate) fn readiness(&self, games: &Games, questions: &QuestionLookup) -> Readiness {
+        let question_count = questions.len();
+        let games_count = games.try_len(STORAGE_TIMEOUT);
+        let checks = Checks {
+            questions: QuestionsCheck {
+                ok: question_count > 0 || !self.questions_required,
+                questions: question_count,
+            },
+            storage: StorageCheck {
+                ok: games_count.is_some(),
+                backend: self.backend,
+                games: games_count,
+            },
+        };
+        let status = if games.is_shutting_down() {
+            "shutting_down"
+        } else if checks.questions.ok && checks.storage.ok {
+            READY
+        } else {
+            "unavailable"
+        };
+        Readiness {
+            status,
+            version: env!("CARGO_PKG_VERSION"),
+            build: Build::current(),
+            uptime_seconds: self.started.elapsed().as_secs(),
+            checks,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Liveness {
+    pub(crate) status: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Build {
+    /// `debug` or `release`
+    profile: &'static str,
+    /// The commit the binary was built from, when `WEIGHTY_BUILD_COMMIT` was set at build time
+    commit: Option<&'static str>,
+}
+
+impl Build {
+    fn current() -> Self {
+        Self {
+            profile: if cfg!(debug_assertions) { "debug" } else { "release" },
+            commit: option_env!("WEIGHTY_BUILD_COMMIT"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct QuestionsCheck {
+    pub(crate) ok: bool,
+    pub(crate) questions: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct StorageCheck {
+    pub(crate) ok: bool,
+    pub(crate) backend: Backend,
+    /// The number of games held, `None` when they could not be read in time
+    pub(crate) games: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Checks {
+    pub(crate) questions: QuestionsCheck,
+    pub(crate) storage: StorageCheck,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Readiness {
+    /// `ready`, `unavailable` or `shutting_down`
+    pub(crate) status: &'static str,
+    pub(crate) version: &'static str,
+    pub(crate) build: Build,
+    pub(crate) uptime_seconds: u64,
+    pub(crate) checks: Checks,
+}
+
+impl Readiness {
+    /// 200 when every check passes and the server is not shutting down, otherwise 503 so
+    /// orchestrators stop routing traffic here
+    pub(crate) fn status(&self) -> Status {
+        if self.status == READY {
+            Status::Ok
+        } else {
+            Status::ServiceUnavailable
+        }
+    }
+}
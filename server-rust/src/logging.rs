@@ -0,0 +1,208 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use parking_lot::Mutex;
+use rand::{distributions::Alphanumeric, Rng};
+use rocket::{
+    config::LoggingLevel,
+    fairing::{Fairing, Info, Kind},
+    http::Header,
+    Data, Request, Response,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    cell::RefCell,
+    fmt::Display,
+    io::Write,
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The header carrying a request's id, accepted from clients and echoed in responses
+const REQUEST_ID_HEADER: &str = "X-Request-Id";
+/// The longest request id accepted from a client
+const MAX_REQUEST_ID_LENGTH: usize = 64;
+/// The length of a generated request id
+const REQUEST_ID_LENGTH: usize = 16;
+/// The target domain events are logged under
+const EVENT_TARGET: &str = "weighty::event";
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+thread_local! {
+    /// The request and game the current thread is working on
+    static CONTEXT: RefCell<Context> = RefCell::new(Context::default());
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum LogFormat {
+    /// One human readable line per message
+    #[default]
+    Text,
+    /// One JSON object per message
+    Json,
+}
+
+#[derive(Default)]
+struct Context {
+    request_id: Option<String>,
+    game_id: Option<String>,
+}
+
+/// Attach `game_id` to every line logged from this thread until the request finishes
+pub(crate) fn set_game(game_id: &str) {
+    CONTEXT.with(|context| context.borrow_mut().game_id = Some(game_id.to_string()));
+}
+
+/// The level filter matching one of Rocket's logging levels
+pub(crate) fn level_filter(level: LoggingLevel) -> LevelFilter {
+    match level {
+        LoggingLevel::Off => LevelFilter::Off,
+        LoggingLevel::Critical => LevelFilter::Warn,
+        LoggingLevel::Normal => LevelFilter::Info,
+        LoggingLevel::Debug => LevelFilter::Trace,
+    }
+}
+
+/// Writes every log line, Rocket's included, tagged with the current request and game
+pub(crate) struct Logger {
+    format: LogFormat,
+    level: LevelFilter,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Logger {
+    fn write(&self, level: Level, target: &str, message: &dyn Display, fields: &[(&str, &dyn Display)]) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_millis());
+        let line = CONTEXT.with(|context| {
+            let context = context.borrow();
+            let ids = [("request_id", &context.request_id), ("game_id", &context.game_id)];
+            let ids = ids.iter().filter_map(|(key, value)| Some((*key, value.as_ref()?)));
+            match self.format {
+                LogFormat::Text => {
+                    let mut line = format!("{} {:<5} {}", timestamp, level, message);
+                    for (key, value) in ids {
+                        line.push_str(&format!(" {}={}", key, value));
+                    }
+                    for (key, value) in fields {
+                        line.push_str(&format!(" {}={}", key, value));
+                    }
+                    line
+                }
+                LogFormat::Json => {
+                    let mut object = Map::new();
+                    object.insert(String::from("timestamp"), Value::from(timestamp as u64));
+                    object.insert(String::from("level"), Value::from(level.as_str()));
+                    object.insert(String::from("target"), Value::from(target));
+                    object.insert(String::from("message"), Value::from(message.to_string()));
+                    for (key, value) in ids {
+                        object.insert(key.to_string(), Value::from(value.as_str()));
+                    }
+                    for (key, value) in fields {
+                        object.insert(key.to_string(), Value::from(value.to_string()));
+                    }
+                    Value::Object(object).to_string()
+                }
+            }
+        });
+        let _ = writeln!(self.out.lock(), "{}", line);
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // Hyper is very chatty so only show it when debugging
+        metadata.level() <= self.level && (self.level == LevelFilter::Trace || !metadata.target().starts_with("hyper"))
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.write(record.level(), record.target(), record.args(), &[]);
+        }
+    }
+
+    fn flush(&self) {
+        let _ = self.out.lock().flush();
+    }
+}
+
+/// Install the logger, writing to `out`. Fails if another logger is already installed, in which
+/// case only domain events are written to `out`.
+pub(crate) fn init(format: LogFormat, level: LevelFilter, out: Box<dyn Write + Send>) -> Result<(), log::SetLoggerError> {
+    let logger = LOGGER.get_or_init(|| Logger {
+        format,
+        level,
+        out: Mutex::new(out),
+    });
+    log::set_logger(logger)?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+/// Log the domain event `name` with structured `fields`
+pub(crate) fn event(name: &str, fields: &[(&str, &dyn Display)]) {
+    if let Some(logger) = LOGGER.get() {
+        if Level::Info <= logger.level {
+            logger.write(Level::Info, EVENT_TARGET, &name, fields);
+        }
+    }
+}
+
+/// The id of the request being handled
+struct RequestId(String);
+
+/// Gives each request an id and sets the logging context for the thread handling it
+pub(crate) struct RequestLogger;
+
+impl Fairing for RequestLogger {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request Logger",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        let request_id = request
+            .headers()
+            .get_one(REQUEST_ID_HEADER)
+            .filter(|id| {
+                !id.is_empty()
+                    && id.len() <= MAX_REQUEST_ID_LENGTH
+                    && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            })
+            .map(String::from)
+            .unwrap_or_else(|| {
+                rand::thread_rng()
+                    .sample_iter(Alphanumeric)
+                    .take(REQUEST_ID_LENGTH)
+                    .map(char::from)
+                    .collect()
+            });
+        let game_id = request
+            .uri()
+            .path()
+            .strip_prefix("/api/v1/game/")
+            .and_then(|path| path.split('/').next())
+            .map(String::from);
+        CONTEXT.with(|context| {
+            *context.borrow_mut() = Context {
+                request_id: Some(request_id.clone()),
+                game_id,
+            }
+        });
+        request.local_cache(|| RequestId(request_id));
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let request_id = request.local_cache(|| RequestId(String::new()));
+        event(
+            "request_finished",
+            &[("method", &request.method()), ("uri", &request.uri()), ("status", &response.status().code)],
+        );
+        response.set_header(Header::new(REQUEST_ID_HEADER, request_id.0.clone()));
+        CONTEXT.with(|context| *context.borrow_mut() = Context::default());
+    }
+}
@@ -1,68 +1,329 @@
 #![feature(proc_macro_hygiene, decl_macro)]
 
+mod admin;
+mod bot;
+mod cli;
+mod config;
+mod export;
+mod filter;
+mod game_code;
+mod health;
+mod logging;
+mod matchmaking;
+mod metrics;
+mod profiles;
 mod question_lookup;
+mod rate_limit;
+mod snapshot;
 #[cfg(test)]
 mod tests;
 mod types;
+mod validation;
+mod word_lookup;
 
+use admin::{Admin, AdminToken, AnswerEdit, Notice, NoticeData, PlayerEdit};
+use bot::Bot;
+use config::{Config, ConfigError};
+use export::{ExportFormat, GameExport};
+use game_code::CaseInsensitiveCodes;
+use filter::{Field, Flag};
+use health::{Health, Liveness, Readiness};
+use logging::{LogFormat, RequestLogger};
+use matchmaking::{MatchStatus, Matchmaking, QueueData};
+use metrics::RequestTimer;
+use profiles::{Leaderboard, Profile, ProfileData, Window, DEFAULT_PER_PAGE};
 use parking_lot::Mutex;
 use question_lookup::QuestionLookup;
-use rocket::http::Method;
-use rocket::{
-    self,
-    config::{Environment, LoggingLevel},
-    delete, get, post, put, routes, Config, State,
-};
+use rate_limit::{RateLimited, RateLimiter};
+use rocket::http::ContentType;
+use rocket::response::{content::Content, status::Custom};
+use rocket::{self, catch, catchers, delete, get, post, put, routes, State};
 use rocket_contrib::{json::Json, serve::StaticFiles};
-use rocket_cors::{AllowedOrigins, CorsOptions};
-use std::path::PathBuf;
+use snapshot::{Drain, Snapshot};
+use validation::{InputLimits, Valid};
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 use structopt::StructOpt;
-use types::{Answer, Game, PlayerData, Result, };
+use types::{
+    Answer, CreateGameData, CreatedGame, GameSummary, Games, Hint, IfMatch, IfNoneMatch, LobbyGame,
+    NewGameData, Notifier, PlayerData, Player, Polled, Result,
+};
+use word_lookup::WordLookup;
+
+type Queue = Mutex<Matchmaking>;
+
+/// Long-polling requests, which each hold a worker while they wait
+struct LongPolls {
+    /// How long a request waits for the game to change
+    timeout: Duration,
+    /// How many requests may wait at once
+    max: usize,
+    waiting: AtomicUsize,
+}
+
+impl LongPolls {
+    /// Wait for the game to move past `since` unless too many requests are already waiting
+    fn wait(&self, notifier: &Notifier, since: u64) {
+        if self.waiting.fetch_add(1, Ordering::SeqCst) < self.max {
+            notifier.wait(since, self.timeout);
+        }
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+    }
+}
 
-type Games = Mutex<types::Games>;
-type Questions = Mutex<QuestionLookup>;
+/// The base URL the server and client are publicly reachable at
+struct PublicUrl(String);
+
+impl PublicUrl {
+    /// The client page which opens with `game_id` filled in
+    fn join_url(&self, game_id: &str) -> String {
+        format!("{}/?game={}", self.0.trim_end_matches('/'), game_id)
+    }
+}
 
 #[get("/heartbeat")]
 fn heartbeat() -> &'static str {
     "heartbeat"
 }
 
-#[put("/game/<game_id>", data = "<player>")]
+/// Whether the server is running at all, for liveness probes
+#[get("/health/live")]
+fn live() -> Json<Liveness> {
+    Json(Liveness { status: "ok" })
+}
+
+/// Whether the server can serve games, for readiness probes
+#[get("/health/ready")]
+fn ready(
+    health: State<Health>,
+    games: State<Arc<Games>>,
+    questions: State<QuestionLookup>,
+) -> Custom<Json<Readiness>> {
+    let readiness = health.readiness(&games, &questions);
+    Custom(readiness.status(), Json(readiness))
+}
+
+#[get("/metrics")]
+fn metrics(games: State<Arc<Games>>, questions: State<QuestionLookup>) -> Content<String> {
+    let body = games.metrics.render(games.len(), questions.len());
+    Content(ContentType::with_params("text", "plain", ("version", "0.0.4")), body)
+}
+
+/// Requests over their rate limit are rewritten here by the `RateLimiter`
+#[get("/rate_limited/<retry_after>")]
+fn rate_limited(retry_after: u64) -> RateLimited {
+    RateLimited(retry_after)
+}
+
+/// Requests which would change anything once shutdown starts are rewritten here by the `Drain`
+#[get("/shutting_down")]
+fn shutting_down() -> Result<()> {
+    Err(types::Error::ShuttingDown)
+}
+
+#[put("/game/<game_id>", data = "<data>")]
 fn create_game(
     game_id: String,
-    player: Json<PlayerData>,
-    games: State<Games>,
-    questions: State<Questions>,
+    data: Valid<CreateGameData>,
+    games: State<Arc<Games>>,
+    questions: State<QuestionLookup>,
+    limits: State<InputLimits>,
 ) -> Result<()> {
-    let mut games = games.lock();
-    let player = player.into_inner();
-    let x = questions.lock().get();
-    let y = questions.lock().get();
-    games.create(game_id, player.player, x, y)
+    limits.game_id(&game_id)?;
+    let data = data.into_inner()?;
+    games.create(game_id, data, questions.get(), questions.get())
+}
+
+#[post("/game", data = "<data>")]
+fn new_game(
+    data: Valid<NewGameData>,
+    games: State<Arc<Games>>,
+    questions: State<QuestionLookup>,
+    public_url: State<PublicUrl>,
+) -> Result<Json<CreatedGame>> {
+    let data = data.into_inner()?;
+    let game_id = games.allocate_id(data.game_id)?;
+    logging::set_game(&game_id);
+    games.create(game_id.clone(), data.game, questions.get(), questions.get())?;
+    let join_url = public_url.join_url(&game_id);
+    Ok(Json(CreatedGame { game_id, join_url }))
+}
+
+#[get("/lobby")]
+fn lobby(games: State<Arc<Games>>) -> Json<Vec<LobbyGame>> {
+    Json(games.lobby())
+}
+
+#[post("/matchmaking", data = "<data>")]
+fn join_queue(
+    data: Valid<QueueData>,
+    queue: State<Queue>,
+    games: State<Arc<Games>>,
+    questions: State<QuestionLookup>,
+) -> Result<Json<MatchStatus>> {
+    let mut queue = queue.lock();
+    queue.join(data.into_inner()?, &games, &questions).map(Json)
+}
+
+#[get("/matchmaking/<player>")]
+fn poll_queue(
+    player: Player,
+    queue: State<Queue>,
+    games: State<Arc<Games>>,
+    questions: State<QuestionLookup>,
+    words: State<WordLookup>,
+) -> Result<Json<MatchStatus>> {
+    let mut queue = queue.lock();
+    queue.poll(&player, &games, &questions, &words).map(Json)
+}
+
+#[delete("/matchmaking/<player>")]
+fn leave_queue(player: Player, queue: State<Queue>) -> Result<()> {
+    queue.lock().leave(&player);
+    Ok(())
+}
+
+#[post("/profile", data = "<data>")]
+fn create_profile(data: Valid<ProfileData>, games: State<Arc<Games>>) -> Result<Json<Profile>> {
+    let mut data = data.into_inner()?;
+    data.name = games.screen(None, games.family_mode, &data.name, Field::Name, data.name.clone())?;
+    games.profiles.lock().create(data).map(Json)
+}
+
+#[get("/profile/<profile_id>")]
+fn profile(profile_id: String, games: State<Arc<Games>>) -> Result<Json<Profile>> {
+    games.profiles.lock().get(&profile_id).cloned().map(Json)
+}
+
+#[get("/leaderboard?<window>&<page>&<per_page>")]
+fn leaderboard(
+    window: Option<Window>,
+    page: Option<usize>,
+    per_page: Option<usize>,
+    games: State<Arc<Games>>,
+) -> Json<Leaderboard> {
+    Json(games.profiles.lock().leaderboard(
+        window.unwrap_or_default(),
+        page.unwrap_or(1),
+        per_page.unwrap_or(DEFAULT_PER_PAGE),
+        types::now(),
+    ))
 }
 
 #[post("/game/<game_id>", data = "<player>")]
-fn join_game(game_id: String, player: Json<PlayerData>, games: State<Games>) -> Result<()> {
-    let mut games = games.lock();
+fn join_game(
+    game_id: String,
+    player: Valid<PlayerData>,
+    games: State<Arc<Games>>,
+    if_match: IfMatch,
+) -> Result<()> {
     let game = games.get(&game_id)?;
-    let player = player.into_inner();
-    game.add_player(player.player)
+    let mut game = game.lock();
+    if_match.check(&game)?;
+    let player = player.into_inner()?;
+    games.join(&game_id, &mut game, player)
 }
 
-#[get("/game/<game_id>")]
-fn game(game_id: String, games: State<Games>) -> Result<Json<Game>> {
-    let mut games = games.lock();
+#[get("/game/<game_id>?<since>")]
+fn game(
+    game_id: String,
+    since: Option<u64>,
+    games: State<Arc<Games>>,
+    long_polls: State<LongPolls>,
+    if_none_match: IfNoneMatch,
+) -> Result<Polled> {
+    if let Some(since) = since {
+        // Wait for the game to move past `since` without holding the games lock
+        let notifier = {
+            let game = games.get(&game_id)?;
+            let game = game.lock();
+            if game.version > since {
+                return Ok(Polled::new(&game, &if_none_match));
+            }
+            game.notifier()
+        };
+        long_polls.wait(&notifier, since);
+    }
     let game = games.get(&game_id)?;
-    // TODO: This clone is ugly
-    Ok(Json(game.clone()))
+    let game = game.lock();
+    Ok(Polled::new(&game, &if_none_match))
+}
+
+#[post("/game/<game_id>/bot", data = "<bot>")]
+fn add_bot(
+    game_id: String,
+    bot: Valid<Bot>,
+    games: State<Arc<Games>>,
+    questions: State<QuestionLookup>,
+    words: State<WordLookup>,
+    if_match: IfMatch,
+) -> Result<()> {
+    let game = games.get(&game_id)?;
+    let mut game = game.lock();
+    if_match.check(&game)?;
+    games.add_bot(&game_id, &mut game, bot.into_inner()?)?;
+    game.bot_answer(&words, &questions)
+}
+
+#[post("/game/<game_id>/hint", data = "<player>")]
+fn hint(
+    game_id: String,
+    player: Valid<PlayerData>,
+    games: State<Arc<Games>>,
+    words: State<WordLookup>,
+    if_match: IfMatch,
+) -> Result<Json<Vec<Hint>>> {
+    let game = games.get(&game_id)?;
+    let mut game = game.lock();
+    if_match.check(&game)?;
+    let player = player.into_inner()?;
+    game.hint(&player.player, &words).map(Json)
+}
+
+#[post("/game/<game_id>/rematch")]
+fn rematch(
+    game_id: String,
+    games: State<Arc<Games>>,
+    questions: State<QuestionLookup>,
+    words: State<WordLookup>,
+    public_url: State<PublicUrl>,
+    if_match: IfMatch,
+) -> Result<Json<CreatedGame>> {
+    if_match.check(&games.get(&game_id)?.lock())?;
+    let game_id = games.rematch(&game_id, questions.get(), questions.get())?;
+    games.get(&game_id)?.lock().bot_answer(&words, &questions)?;
+    let join_url = public_url.join_url(&game_id);
+    Ok(Json(CreatedGame { game_id, join_url }))
 }
 
 #[post("/game/<game_id>/answer", data = "<answer>")]
-fn answer(game_id: String, answer: Json<Answer>, games: State<Games>, questions: State<Questions>) -> Result<()> {
-    let mut games = games.lock();
+fn answer(
+    game_id: String,
+    answer: Valid<Answer>,
+    games: State<Arc<Games>>,
+    questions: State<QuestionLookup>,
+    words: State<WordLookup>,
+    if_match: IfMatch,
+) -> Result<()> {
     let game = games.get(&game_id)?;
-    let answer = answer.into_inner();
-    game.answer(answer, questions)
+    let mut game = game.lock();
+    if_match.check(&game)?;
+    let answer = answer.into_inner()?;
+    let completed = game.completed_rounds();
+    games.answer(&game_id, &mut game, answer, &questions)?;
+    games.metrics.answered(&game_id, completed + 1);
+    game.bot_answer(&words, &questions)?;
+    if game.completed_rounds() > completed {
+        games.metrics.rounds_completed(&game_id, game.completed_rounds() - completed, game.finished);
+    }
+    games.record_stats(&mut game);
+    Ok(())
 }
 
 // #[delete("/game/<game_id>/exit", data = "<player>")]
@@ -73,10 +334,107 @@ fn answer(game_id: String, answer: Json<Answer>, games: State<Games>, questions:
 //     game.remove_player(player.player)
 // }
 
+#[get("/game/<game_id>/export?<format>")]
+fn export_game(
+    game_id: String,
+    format: Option<ExportFormat>,
+    games: State<Arc<Games>>,
+) -> Result<Content<String>> {
+    let export = games.export(&game_id)?;
+    Ok(match format.unwrap_or(ExportFormat::Json) {
+        ExportFormat::Json => Content(ContentType::JSON, export.to_json()),
+        ExportFormat::Csv => Content(ContentType::CSV, export.to_csv()),
+    })
+}
+
+#[post("/import", data = "<export>")]
+fn import_game(_admin: Admin, export: Valid<GameExport>, games: State<Arc<Games>>) -> Result<()> {
+    games.import(export.into_inner()?)
+}
+
 #[delete("/game/<game_id>")]
-fn delete_game(game_id: String, games: State<Games>) {
-    let mut games = games.lock();
-    games.delete(&game_id)
+fn delete_game(game_id: String, games: State<Arc<Games>>, if_match: IfMatch) -> Result<()> {
+    if let Ok(game) = games.get(&game_id) {
+        if_match.check(&game.lock())?;
+    }
+    games.delete(&game_id);
+    Ok(())
+}
+
+/// The current maintenance notice, 404 if there is none
+#[get("/notice")]
+fn notice(games: State<Arc<Games>>) -> Option<Json<Notice>> {
+    games.notice.lock().clone().map(Json)
+}
+
+/// Requests refused by the `Admin` guard get the same JSON error as the rest of the API
+#[catch(401)]
+fn unauthorized() -> types::Error {
+    types::Error::Unauthorized
+}
+
+#[get("/admin/games")]
+fn admin_games(_admin: Admin, games: State<Arc<Games>>) -> Result<Json<Vec<GameSummary>>> {
+    Ok(Json(games.summaries()))
+}
+
+#[post("/admin/game/<game_id>/advance")]
+fn admin_advance(
+    _admin: Admin,
+    game_id: String,
+    games: State<Arc<Games>>,
+    questions: State<QuestionLookup>,
+    words: State<WordLookup>,
+) -> Result<()> {
+    let game = games.get(&game_id)?;
+    let mut game = game.lock();
+    let completed = game.completed_rounds();
+    game.force_advance(&questions)?;
+    game.bot_answer(&words, &questions)?;
+    games.metrics.rounds_completed(&game_id, game.completed_rounds() - completed, game.finished);
+    games.record_stats(&mut game);
+    Ok(())
+}
+
+#[put("/admin/game/<game_id>/answer", data = "<edit>")]
+fn admin_answer(_admin: Admin, game_id: String, edit: Valid<AnswerEdit>, games: State<Arc<Games>>) -> Result<()> {
+    let edit = edit.into_inner()?;
+    let game = games.get(&game_id)?;
+    let mut game = game.lock();
+    game.moderate_answer(edit.round, &edit.player, edit.answer)
+}
+
+#[put("/admin/game/<game_id>/player", data = "<edit>")]
+fn admin_player(_admin: Admin, game_id: String, edit: Valid<PlayerEdit>, games: State<Arc<Games>>) -> Result<()> {
+    let edit = edit.into_inner()?;
+    let game = games.get(&game_id)?;
+    let mut game = game.lock();
+    game.rename_player(&edit.player, edit.name)
+}
+
+#[put("/admin/notice", data = "<data>")]
+fn admin_notice(_admin: Admin, data: Json<NoticeData>, games: State<Arc<Games>>) -> Result<Json<Notice>> {
+    let notice = Notice::new(data.into_inner())?;
+    *games.notice.lock() = Some(notice.clone());
+    logging::event("notice_posted", &[("message", &notice.message)]);
+    Ok(Json(notice))
+}
+
+#[delete("/admin/notice")]
+fn admin_clear_notice(_admin: Admin, games: State<Arc<Games>>) -> Result<()> {
+    *games.notice.lock() = None;
+    Ok(())
+}
+
+#[get("/admin/flags")]
+fn admin_flags(_admin: Admin, games: State<Arc<Games>>) -> Result<Json<Vec<Flag>>> {
+    Ok(Json(games.flags.lock().iter().cloned().collect()))
+}
+
+#[delete("/admin/flags")]
+fn admin_clear_flags(_admin: Admin, games: State<Arc<Games>>) -> Result<()> {
+    games.flags.lock().clear();
+    Ok(())
 }
 
 // #[get("/game/<game_id>/score")]
@@ -111,86 +469,311 @@ fn delete_game(game_id: String, games: State<Games>) {
 //     Ok(())
 // }
 
-fn rocket(opt: Option<Opt>) -> rocket::Rocket {
+fn rocket(config: Option<Config>) -> rocket::Rocket {
+    let rocket = match &config {
+        Some(config) => {
+            // Configure the Rocket instance
+            let rocket_config = rocket::Config::build(config.server.environment())
+                .address(config.server.host.clone())
+                .port(config.server.port)
+                .log_level(config.server.log_level())
+                .workers(config.server.workers())
+                .finalize()
+                .expect("to build Rocket Config");
+            rocket::custom(rocket_config)
+        }
+        None => rocket::ignite(),
+    };
+    let config = config.unwrap_or_default();
     let mut questions = QuestionLookup::default();
-    let rocket = if let Some(opt) = opt {
-        if let Some(questions_file) = opt.questions_file {
-            // Populate the questions
-            if let Err(e) = questions.populate_from_file(&questions_file) {
-                eprintln!(
-                    "Failed to populate questions from file {:?}, err: {}",
-                    questions_file, e
-                );
+    if let Some(questions_file) = &config.questions.file {
+        // Populate the questions
+        if let Err(e) = questions.populate_from_file(questions_file) {
+            eprintln!(
+                "Failed to populate questions from file {:?}, err: {}",
+                questions_file, e
+            );
+            std::process::exit(1);
+        }
+    }
+    let mut words = WordLookup::bundled();
+    if let Some(words_file) = &config.questions.words_file {
+        // Populate the words bots answer with in place of the bundled ones
+        words = WordLookup::default();
+        if let Err(e) = words.populate_from_file(words_file) {
+            eprintln!(
+                "Failed to populate words from file {:?}, err: {}",
+                words_file, e
+            );
+            std::process::exit(1);
+        }
+    }
+    let mut games = Games::default();
+    games.profiles.get_mut().margin_multiplier = config.game.rating_margin;
+    games.default_rules = config.game.rules.clone();
+    games.family_mode = config.filter.family_mode;
+    games.limits = config.input_limits;
+    games.filter.mode = config.filter.mode;
+    if let Some(words_file) = &config.filter.words_file {
+        // Populate the blocked words
+        if let Err(e) = games.filter.populate_from_file(words_file) {
+            eprintln!(
+                "Failed to populate blocked words from file {:?}, err: {}",
+                words_file, e
+            );
+            std::process::exit(1);
+        }
+    }
+    if let Some(path) = &config.storage.snapshot {
+        // Restore the games saved when the server last shut down
+        match Snapshot::read(path).and_then(|snapshot| snapshot.map_or(Ok(0), |s| s.restore(&games))) {
+            Ok(restored) => logging::event("snapshot_restored", &[("games", &restored)]),
+            Err(e) => {
+                eprintln!("Failed to restore snapshot {:?}, err: {}", path, e);
                 std::process::exit(1);
             }
         }
-        // Configure the Rocket instance
-        let config = Config::build(Environment::Staging)
-            .address(opt.host)
-            .port(opt.port)
-            .log_level(opt.log_level)
-            .finalize()
-            .expect("to build Rocket Config");
-        rocket::custom(config)
-    } else {
-        rocket::ignite()
+    }
+    let cors = match config.cors.to_cors() {
+        Ok(cors) => cors,
+        Err(e) => {
+            eprintln!("Invalid CORS configuration, err: {}", e);
+            std::process::exit(1);
+        }
     };
-    let cors = CorsOptions::default()
-        .allowed_origins(AllowedOrigins::all())
-        .allowed_methods(
-            vec![
-                Method::Get,
-                Method::Post,
-                Method::Patch,
-                Method::Put,
-                Method::Delete,
-            ]
-            .into_iter()
-            .map(From::from)
-            .collect(),
-        )
-        .allow_credentials(true);
-
-    rocket::ignite().attach(cors.to_cors().unwrap());
     rocket
-        .attach(cors.to_cors().unwrap())
+        .register(catchers![unauthorized])
+        .attach(CaseInsensitiveCodes)
+        .attach(RequestLogger)
+        .attach(cors)
+        .attach(Drain)
+        .attach(RateLimiter::new(config.rate_limits))
+        .attach(RequestTimer)
         .mount("/", StaticFiles::from("../client-svelte/build/index.html"))
+        .mount("/", routes![metrics])
         .mount(
             "/api/v1",
             routes![
                 heartbeat,
+                live,
+                ready,
+                rate_limited,
+                shutting_down,
+                new_game,
+                lobby,
+                join_queue,
+                poll_queue,
+                leave_queue,
+                create_profile,
+                profile,
+                leaderboard,
                 create_game,
                 join_game,
                 game,
+                add_bot,
+                hint,
+                rematch,
                 answer,
+                export_game,
+                import_game,
                 delete_game,
+                notice,
+                admin_games,
+                admin_advance,
+                admin_answer,
+                admin_player,
+                admin_notice,
+                admin_clear_notice,
+                admin_flags,
+                admin_clear_flags,
             ],
         )
-        .manage(Mutex::new(questions))
-        .manage(Games::default())
+        .manage(AdminToken(config.admin.token.clone()))
+        .manage(config.input_limits)
+        .manage(Health::new(config.storage.backend, config.questions.file.is_some()))
+        .manage(questions)
+        .manage(words)
+        .manage(LongPolls {
+            timeout: Duration::from_secs(config.server.long_poll_timeout),
+            max: config.server.max_long_polls(),
+            waiting: AtomicUsize::new(0),
+        })
+        .manage(PublicUrl(config.server.public_url))
+        .manage(Mutex::new(Matchmaking::new(Duration::from_secs(config.game.matchmaking_timeout))))
+        .manage(Arc::new(games))
 }
 
+/// Command line overrides for the configuration
 #[derive(Debug, StructOpt)]
 struct Opt {
+    /// A TOML config file, `$WEIGHTY_CONFIG` if not given. `WEIGHTY_*` environment variables
+    /// override the file and flags override both.
+    #[structopt(long = "config", short = "c")]
+    config: Option<PathBuf>,
     /// The path to a file containing newline delimited questions.
     #[structopt(long = "questions-file")]
     questions_file: Option<PathBuf>,
+    /// The path to a file of candidate answers for bots, one `Category: word, word` per line. The
+    /// bundled `words.txt` is used if not given.
+    #[structopt(long = "words-file")]
+    words_file: Option<PathBuf>,
+    /// The path to a file of words blocked in family mode games, one per line.
+    #[structopt(long = "blocked-words-file")]
+    blocked_words_file: Option<PathBuf>,
+    /// Put games in family mode unless they are created otherwise.
+    #[structopt(long = "family-mode")]
+    family_mode: bool,
+    /// A file games are saved to on shutdown and restored from on start.
+    #[structopt(long = "snapshot")]
+    snapshot: Option<PathBuf>,
+    /// The base URL the server and client are publicly reachable at, used to build join URLs.
+    #[structopt(long = "public-url")]
+    public_url: Option<String>,
+    /// How long a player waits in the matchmaking queue before their fallback is used, in seconds.
+    #[structopt(long = "matchmaking-timeout")]
+    matchmaking_timeout: Option<u64>,
+    /// How long a `?since=` request waits for the game to change before responding, in seconds.
+    #[structopt(long = "long-poll-timeout")]
+    long_poll_timeout: Option<u64>,
+    /// How many requests each IP address may make per rate limit window, 0 for no limit.
+    #[structopt(long = "ip-rate-limit")]
+    ip_rate_limit: Option<u32>,
+    /// How many changes may be made to each game per rate limit window, 0 for no limit.
+    #[structopt(long = "game-rate-limit")]
+    game_rate_limit: Option<u32>,
+    /// The length of a rate limit window, in seconds.
+    #[structopt(long = "rate-limit-window")]
+    rate_limit_window: Option<u64>,
+    /// Rate limit clients by the `X-Real-IP` header. Only set this behind a reverse proxy which
+    /// sets the header.
+    #[structopt(long = "trust-proxy")]
+    trust_proxy: bool,
+    /// An origin allowed to make cross-origin requests, `*` for any. May be repeated.
+    #[structopt(long = "allowed-origin")]
+    allowed_origins: Vec<String>,
+    /// The methods allowed in cross-origin requests, comma separated.
+    #[structopt(long = "allowed-methods", use_delimiter = true)]
+    allowed_methods: Vec<String>,
+    /// Allow cross-origin requests to include credentials such as cookies.
+    #[structopt(long = "allow-credentials")]
+    allow_credentials: bool,
+    /// Scale rating changes by the margin of captured letters.
+    #[structopt(long = "rating-margin")]
+    rating_margin: bool,
     /// An IP address or host the application will listen on.
-    #[structopt(long = "host", short = "H", default_value = "0.0.0.0")]
-    host: String,
+    #[structopt(long = "host", short = "H")]
+    host: Option<String>,
     /// A port number to listen on.
-    #[structopt(long = "port", short = "P", default_value = "8172")]
-    port: u16,
+    #[structopt(long = "port", short = "P")]
+    port: Option<u16>,
     /// The log level.
     #[structopt(
-        default_value = "normal",
         long = "log-level",
         possible_values = &["off", "debug", "normal", "critical"]
     )]
-    log_level: LoggingLevel,
+    log_level: Option<String>,
+    /// The format of log lines.
+    #[structopt(long = "log-format", possible_values = &["text", "json"])]
+    log_format: Option<String>,
+}
+
+impl Opt {
+    /// Load the configuration and apply the flags which were given on top of it
+    fn load(self) -> std::result::Result<Config, ConfigError> {
+        let mut config = Config::load(self.config.as_deref())?;
+        let server = &mut config.server;
+        server.host = self.host.unwrap_or(server.host.clone());
+        server.port = self.port.unwrap_or(server.port);
+        server.log_level = self.log_level.unwrap_or(server.log_level.clone());
+        if let Some(format) = self.log_format {
+            server.log_format = if format == "json" { LogFormat::Json } else { LogFormat::Text };
+        }
+        server.public_url = self.public_url.unwrap_or(server.public_url.clone());
+        server.long_poll_timeout = self.long_poll_timeout.unwrap_or(server.long_poll_timeout);
+        let game = &mut config.game;
+        game.matchmaking_timeout = self.matchmaking_timeout.unwrap_or(game.matchmaking_timeout);
+        game.rating_margin |= self.rating_margin;
+        let questions = &mut config.questions;
+        questions.file = self.questions_file.or(questions.file.take());
+        questions.words_file = self.words_file.or(questions.words_file.take());
+        config.storage.snapshot = self.snapshot.or(config.storage.snapshot.take());
+        let filter = &mut config.filter;
+        filter.words_file = self.blocked_words_file.or(filter.words_file.take());
+        filter.family_mode |= self.family_mode;
+        let limits = &mut config.rate_limits;
+        limits.per_ip = self.ip_rate_limit.unwrap_or(limits.per_ip);
+        limits.per_game = self.game_rate_limit.unwrap_or(limits.per_game);
+        limits.window = self.rate_limit_window.unwrap_or(limits.window);
+        limits.trust_proxy |= self.trust_proxy;
+        let cors = &mut config.cors;
+        if !self.allowed_origins.is_empty() {
+            cors.allowed_origins = self.allowed_origins;
+        }
+        if !self.allowed_methods.is_empty() {
+            cors.allowed_methods = self.allowed_methods;
+        }
+        cors.allow_credentials |= self.allow_credentials;
+        Ok(config)
+    }
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "weighty-inquiry")]
+struct Cli {
+    #[structopt(flatten)]
+    opt: Opt,
+    /// Print the merged configuration as TOML and exit.
+    #[structopt(long = "print-config")]
+    print_config: bool,
+    #[structopt(subcommand)]
+    command: Option<cli::Command>,
 }
 
 fn main() {
-    let opt = Opt::from_args();
-    rocket(Some(opt)).launch();
+    let cli = Cli::from_args();
+    match cli.command {
+        None | Some(cli::Command::Serve) => {
+            let config = match cli.opt.load() {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            };
+            if cli.print_config {
+                print!("{}", config.to_toml());
+            }
+            if let Err(e) = config.validate() {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            if !cli.print_config {
+                let level = logging::level_filter(config.server.log_level());
+                if let Err(e) = logging::init(config.server.log_format, level, Box::new(std::io::stderr())) {
+                    eprintln!("Failed to initialize logging, err: {}", e);
+                }
+                let snapshot = config.storage.snapshot.clone();
+                let rocket = rocket(Some(config));
+                let games = rocket.state::<Arc<Games>>().expect("to manage the games");
+                if let Err(e) = snapshot::handle_signals(Arc::clone(games), snapshot) {
+                    eprintln!("Failed to handle shutdown signals, err: {}", e);
+                    std::process::exit(1);
+                }
+                rocket.launch();
+            }
+        }
+        Some(command) => {
+            let config = match cli.opt.load() {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            };
+            if let Err(e) = command.run(&config) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
 }
@@ -0,0 +1,221 @@
+use crate::{
+    bot::{self, Bot, Difficulty},
+    logging,
+    question_lookup::QuestionLookup,
+    filter::Field,
+    types::{CreateGameData, Error, Games, Player, PlayerData, Result},
+    word_lookup::WordLookup,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+/// What to do with a player nobody was paired with before the timeout
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum Fallback {
+    /// Start a game against a bot
+    Bot {
+        #[serde(default)]
+        difficulty: Difficulty,
+    },
+    /// Leave the queue
+    #[default]
+    Cancel,
+}
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct QueueData {
+    /// The player looking for a game
+    pub(crate) player: Player,
+    #[serde(default)]
+    pub(crate) fallback: Fallback,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub(crate) enum MatchStatus {
+    /// Still waiting for an opponent
+    Waiting,
+    /// Paired into the given game
+    Matched { game_id: String },
+    /// Nobody was found before the timeout
+    Cancelled,
+}
+
+struct Ticket {
+    player: Player,
+    fallback: Fallback,
+    since: Instant,
+}
+
+/// A queue pairing waiting players into fresh games
+pub(crate) struct Matchmaking {
+    /// How long a player waits before their fallback is used
+    timeout: Duration,
+    waiting: VecDeque<Ticket>,
+    /// Games created for players who have not polled since being paired and when they were paired,
+    /// forgotten after `timeout`
+    matched: HashMap<Player, (String, Instant)>,
+}
+
+fn create_game(games: &Games, player: Player, questions: &QuestionLookup) -> Result<String> {
+    let game_id = games.allocate_id(None)?;
+    let data = CreateGameData {
+        player,
+        profile: None,
+        rules: None,
+        public: false,
+        best_of: 1,
+        family_mode: None,
+    };
+    let x = questions.get();
+    let y = questions.get();
+    games.create(game_id.clone(), data, x, y)?;
+    logging::set_game(&game_id);
+    logging::event("matched", &[]);
+    Ok(game_id)
+}
+
+/// Create a game between `player` and `opponent`, deleting it again if the opponent cannot join
+fn start_game(games: &Games, player: Player, opponent: Player, questions: &QuestionLookup) -> Result<String> {
+    let game_id = create_game(games, player, questions)?;
+    let data = PlayerData {
+        player: opponent,
+        profile: None,
+    };
+    let joined = games.get(&game_id).and_then(|game| games.join(&game_id, &mut game.lock(), data));
+    if let Err(e) = joined {
+        games.delete(&game_id);
+        return Err(e);
+    }
+    Ok(game_id)
+}
+
+/// Create a game between `player` and `bot`, deleting it again if the bot cannot join
+fn start_bot_game(
+    games: &Games,
+    player: Player,
+    bot: Bot,
+    questions: &QuestionLookup,
+    words: &WordLookup,
+) -> Result<String> {
+    let game_id = create_game(games, player, questions)?;
+    let game = games.get(&game_id)?;
+    let mut game = game.lock();
+    let joined = games
+        .add_bot(&game_id, &mut game, bot)
+        .and_then(|()| game.bot_answer(words, questions));
+    if let Err(e) = joined {
+        drop(game);
+        games.delete(&game_id);
+        return Err(e);
+    }
+    Ok(game_id)
+}
+
+impl Matchmaking {
+    pub(crate) fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            waiting: VecDeque::new(),
+            matched: HashMap::new(),
+        }
+    }
+
+    /// Pair `player` with the longest waiting player or add them to the queue
+    pub(crate) fn join(
+        &mut self,
+        data: QueueData,
+        games: &Games,
+        questions: &QuestionLookup,
+    ) -> Result<MatchStatus> {
+        let player = data.player;
+        games.screen(None, games.family_mode, &player, Field::Name, player.clone())?;
+        self.forget_unpolled();
+        if self.waiting.iter().any(|ticket| ticket.player == player) || self.matched.contains_key(&player) {
+            return Err(Error::PlayerConflict);
+        }
+        // Players who have waited too long get their fallback when they next poll instead
+        let timeout = self.timeout;
+        let opponent = self.waiting.iter().position(|ticket| ticket.since.elapsed() < timeout);
+        match opponent {
+            Some(index) => {
+                let opponent = self.waiting.remove(index).expect("ticket to exist");
+                match start_game(games, opponent.player.clone(), player, questions) {
+                    Ok(game_id) => {
+                        self.matched.insert(opponent.player, (game_id.clone(), Instant::now()));
+                        Ok(MatchStatus::Matched { game_id })
+                    }
+                    Err(e) => {
+                        // The opponent keeps their place for the next player
+                        self.waiting.insert(index, opponent);
+                        Err(e)
+                    }
+                }
+            }
+            None => {
+                self.waiting.push_back(Ticket {
+                    player,
+                    fallback: data.fallback,
+                    since: Instant::now(),
+                });
+                Ok(MatchStatus::Waiting)
+            }
+        }
+    }
+
+    /// Check whether `player` has been paired, applying their fallback once they have waited too long
+    pub(crate) fn poll(
+        &mut self,
+        player: &Player,
+        games: &Games,
+        questions: &QuestionLookup,
+        words: &WordLookup,
+    ) -> Result<MatchStatus> {
+        self.forget_unpolled();
+        if let Some((game_id, _)) = self.matched.remove(player) {
+            return Ok(MatchStatus::Matched { game_id });
+        }
+        let index = self
+            .waiting
+            .iter()
+            .position(|ticket| &ticket.player == player)
+            .ok_or(Error::PlayerNotFound)?;
+        if self.waiting[index].since.elapsed() < self.timeout {
+            return Ok(MatchStatus::Waiting);
+        }
+        let ticket = self.waiting.remove(index).expect("ticket to exist");
+        match ticket.fallback {
+            Fallback::Cancel => Ok(MatchStatus::Cancelled),
+            Fallback::Bot { difficulty } => {
+                let bot = Bot {
+                    name: bot::name_against(&ticket.player),
+                    difficulty,
+                };
+                match start_bot_game(games, ticket.player.clone(), bot, questions, words) {
+                    Ok(game_id) => Ok(MatchStatus::Matched { game_id }),
+                    Err(e) => {
+                        // Keep the player queued so they can poll again or leave
+                        self.waiting.insert(index, ticket);
+                        Err(e)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Forget the games of paired players who have not polled within `timeout` of being paired
+    fn forget_unpolled(&mut self) {
+        let timeout = self.timeout;
+        self.matched.retain(|_, (_, paired)| paired.elapsed() < timeout);
+    }
+
+    /// Remove `player` from the queue
+    pub(crate) fn leave(&mut self, player: &Player) {
+        self.waiting.retain(|ticket| &ticket.player != player);
+        self.matched.remove(player);
+    }
+}
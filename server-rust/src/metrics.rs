@@ -0,0 +1,236 @@
+use crate::types::Games;
+use parking_lot::Mutex;
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    Data, Request, Response, State,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// Upper bounds of the HTTP latency buckets, in seconds
+const HTTP_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0, 30.0];
+/// Upper bounds of the answer latency buckets, in seconds
+const ANSWER_BUCKETS: &[f64] = &[1.0, 2.0, 5.0, 10.0, 15.0, 20.0, 30.0, 45.0, 60.0, 120.0, 300.0];
+/// The round labels answer latency is split by, later rounds sharing a label to bound the series
+const ROUND_LABELS: &[&str] = &["1", "2", "3", "4", "5-9", "10+"];
+
+/// The index into `ROUND_LABELS` of the label for `round`, counting from 1
+fn round_label(round: usize) -> usize {
+    match round {
+        0..=4 => round.saturating_sub(1),
+        5..=9 => 4,
+        _ => 5,
+    }
+}
+
+struct Histogram {
+    bounds: &'static [f64],
+    /// The number of observations in each bucket, not cumulative
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = self.bounds.iter().position(|bound| seconds <= *bound) {
+            self.counts[bucket] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    /// Write the histogram in the Prometheus text format, `labels` being empty or ending in a comma
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{{}le=\"{}\"}} {}", name, labels, bound, cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{{{}le=\"+Inf\"}} {}", name, labels, self.count);
+        let labels = match labels.trim_end_matches(',') {
+            "" => String::new(),
+            labels => format!("{{{}}}", labels),
+        };
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, self.count);
+    }
+}
+
+/// Counters describing the server's activity, exposed at `/metrics`
+pub(crate) struct Metrics {
+    games_created: AtomicU64,
+    games_deleted: AtomicU64,
+    rounds_completed: AtomicU64,
+    /// How long players take to answer after their round starts, by `ROUND_LABELS`
+    answer_latency: Mutex<Vec<Histogram>>,
+    /// When the current round of each game started
+    round_started: Mutex<HashMap<String, Instant>>,
+    errors: Mutex<BTreeMap<String, u64>>,
+    /// Request latency by method and route name
+    http_latency: Mutex<BTreeMap<(String, String), Histogram>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            games_created: AtomicU64::new(0),
+            games_deleted: AtomicU64::new(0),
+            rounds_completed: AtomicU64::new(0),
+            answer_latency: Mutex::new(ROUND_LABELS.iter().map(|_| Histogram::new(ANSWER_BUCKETS)).collect()),
+            round_started: Mutex::new(HashMap::new()),
+            errors: Mutex::new(BTreeMap::new()),
+            http_latency: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl Metrics {
+    pub(crate) fn game_created(&self, game_id: &str) {
+        self.games_created.fetch_add(1, Ordering::Relaxed);
+        self.round_started.lock().insert(game_id.to_string(), Instant::now());
+    }
+
+    pub(crate) fn game_deleted(&self, game_id: &str) {
+        self.games_deleted.fetch_add(1, Ordering::Relaxed);
+        self.round_started.lock().remove(game_id);
+    }
+
+    /// A player answered `round`, the current round of `game_id`
+    pub(crate) fn answered(&self, game_id: &str, round: usize) {
+        if let Some(started) = self.round_started.lock().get(game_id) {
+            self.answer_latency.lock()[round_label(round)].observe(started.elapsed());
+        }
+    }
+
+    /// `rounds` rounds of `game_id` were completed and the next one started, unless the game is
+    /// `finished`
+    pub(crate) fn rounds_completed(&self, game_id: &str, rounds: usize, finished: bool) {
+        self.rounds_completed.fetch_add(rounds as u64, Ordering::Relaxed);
+        let mut round_started = self.round_started.lock();
+        if finished {
+            round_started.remove(game_id);
+        } else {
+            round_started.insert(game_id.to_string(), Instant::now());
+        }
+    }
+
+    /// The number of games whose current round is being timed
+    #[cfg(test)]
+    pub(crate) fn rounds_in_progress(&self) -> usize {
+        self.round_started.lock().len()
+    }
+
+    /// An error of the variant named `error` was returned
+    pub(crate) fn error(&self, error: &str) {
+        *self.errors.lock().entry(error.to_string()).or_default() += 1;
+    }
+
+    fn request(&self, method: &str, route: &str, duration: Duration) {
+        self.http_latency
+            .lock()
+            .entry((method.to_string(), route.to_string()))
+            .or_insert_with(|| Histogram::new(HTTP_BUCKETS))
+            .observe(duration);
+    }
+
+    /// Every metric in the Prometheus text format
+    pub(crate) fn render(&self, active_games: usize, questions: usize) -> String {
+        let mut out = String::new();
+        let values = [
+            ("weighty_games_active", "gauge", "Games currently held by the server.", active_games as u64),
+            (
+                "weighty_games_created_total",
+                "counter",
+                "Games created, including rematches and imports.",
+                self.games_created.load(Ordering::Relaxed),
+            ),
+            (
+                "weighty_games_deleted_total",
+                "counter",
+                "Games deleted.",
+                self.games_deleted.load(Ordering::Relaxed),
+            ),
+            (
+                "weighty_rounds_completed_total",
+                "counter",
+                "Rounds both players have answered.",
+                self.rounds_completed.load(Ordering::Relaxed),
+            ),
+            ("weighty_questions", "gauge", "Questions in the question bank.", questions as u64),
+        ];
+        for (name, kind, help, value) in values {
+            header(&mut out, name, kind, help);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+        let name = "weighty_answer_latency_seconds";
+        header(&mut out, name, "histogram", "Time from the start of a round to each player's answer by round.");
+        for (round, histogram) in ROUND_LABELS.iter().zip(self.answer_latency.lock().iter()) {
+            if histogram.count > 0 {
+                histogram.render(&mut out, name, &format!("round=\"{}\",", round));
+            }
+        }
+        let name = "weighty_errors_total";
+        header(&mut out, name, "counter", "Errors returned by variant.");
+        for (error, count) in self.errors.lock().iter() {
+            let _ = writeln!(out, "{}{{error=\"{}\"}} {}", name, error, count);
+        }
+        let name = "weighty_http_request_duration_seconds";
+        header(&mut out, name, "histogram", "Time taken to handle requests by method and route.");
+        for ((method, route), histogram) in self.http_latency.lock().iter() {
+            let labels = format!("method=\"{}\",route=\"{}\",", method, route);
+            histogram.render(&mut out, name, &labels);
+        }
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+}
+
+/// When a request started being handled
+struct RequestStart(Instant);
+
+/// Records how long each request takes to the `Metrics` of the managed `Games`
+pub(crate) struct RequestTimer;
+
+impl Fairing for RequestTimer {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request Timer",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        request.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    fn on_response(&self, request: &Request, _: &mut Response) {
+        let start = request.local_cache(|| RequestStart(Instant::now()));
+        let route = request.route().and_then(|route| route.name).unwrap_or("unmatched");
+        if let rocket::Outcome::Success(games) = request.guard::<State<Arc<Games>>>() {
+            games
+                .metrics
+                .request(request.method().as_str(), route, start.0.elapsed());
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use crate::types::{Error, Result};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+pub(crate) type ProfileId = String;
+
+/// The length of a generated profile id
+const PROFILE_ID_LENGTH: usize = 16;
+/// How many of a player's most used letters are reported as favorites
+const FAVORITE_LETTERS: usize = 3;
+/// The rating a new profile starts with
+const INITIAL_RATING: f64 = 1500.0;
+/// The largest rating change a single game can cause without the margin multiplier
+const K_FACTOR: f64 = 32.0;
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+/// The number of entries on a leaderboard page when none is requested
+pub(crate) const DEFAULT_PER_PAGE: usize = 20;
+const MAX_PER_PAGE: usize = 100;
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct ProfileData {
+    /// The display name of the profile
+    pub(crate) name: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub(crate) struct Stats {
+    pub(crate) games_played: usize,
+    pub(crate) wins: usize,
+    /// The number of letters captured across all games
+    pub(crate) letters_captured: usize,
+    /// The number of answers given across all games
+    pub(crate) answers: usize,
+    /// The number of characters across all answers
+    pub(crate) answer_length: usize,
+    pub(crate) average_answer_length: f64,
+    /// How often each letter was used in an answer
+    pub(crate) letter_counts: BTreeMap<char, usize>,
+    /// The most used letters, most used first
+    pub(crate) favorite_letters: Vec<char>,
+}
+
+/// The result of a single finished game for one player
+pub(crate) struct GameResult<'a> {
+    pub(crate) won: bool,
+    pub(crate) letters_captured: usize,
+    pub(crate) answers: Vec<&'a str>,
+}
+
+impl Stats {
+    fn record(&mut self, result: &GameResult) {
+        self.games_played += 1;
+        if result.won {
+            self.wins += 1;
+        }
+        self.letters_captured += result.letters_captured;
+        for answer in &result.answers {
+            self.answers += 1;
+            self.answer_length += answer.chars().count();
+            for letter in answer.chars().filter(char::is_ascii_alphabetic) {
+                *self.letter_counts.entry(letter.to_ascii_lowercase()).or_default() += 1;
+            }
+        }
+        if self.answers > 0 {
+            self.average_answer_length = self.answer_length as f64 / self.answers as f64;
+        }
+        let mut letters = self.letter_counts.iter().collect::<Vec<_>>();
+        letters.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        self.favorite_letters = letters
+            .into_iter()
+            .take(FAVORITE_LETTERS)
+            .map(|(letter, _)| *letter)
+            .collect();
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub(crate) struct RatingChange {
+    /// When the game finished in seconds since the Unix epoch
+    pub(crate) at: u64,
+    pub(crate) change: f64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub(crate) struct Profile {
+    pub(crate) id: ProfileId,
+    pub(crate) name: String,
+    pub(crate) stats: Stats,
+    pub(crate) rating: f64,
+    /// The rating change from each rated game, oldest first
+    pub(crate) rating_history: Vec<RatingChange>,
+}
+
+impl Profile {
+    /// The rating gained since `since` and the number of rated games it came from
+    fn change_since(&self, since: u64) -> (f64, usize) {
+        self.rating_history
+            .iter()
+            .filter(|change| change.at >= since)
+            .fold((0.0, 0), |(total, games), change| (total + change.change, games + 1))
+    }
+}
+
+/// The period a leaderboard ranks players over
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Window {
+    /// Rank by current rating
+    #[default]
+    AllTime,
+    /// Rank by rating gained in the current calendar month (UTC)
+    Month,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub(crate) struct LeaderboardEntry {
+    /// The position on the leaderboard starting at 1
+    pub(crate) rank: usize,
+    /// The profile's name. Its id is left out as anyone holding it can play under the profile.
+    pub(crate) name: String,
+    pub(crate) rating: f64,
+    /// The rating gained within the window
+    pub(crate) change: f64,
+    /// The number of rated games within the window
+    pub(crate) games: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub(crate) struct Leaderboard {
+    pub(crate) window: Window,
+    /// The page starting at 1
+    pub(crate) page: usize,
+    pub(crate) per_page: usize,
+    /// The number of ranked players across all pages
+    pub(crate) total: usize,
+    pub(crate) entries: Vec<LeaderboardEntry>,
+}
+
+/// The start of the calendar month (UTC) containing `now`, both in seconds since the Unix epoch
+pub(crate) fn month_start(now: u64) -> u64 {
+    // Day of the month using the civil from days algorithm by Howard Hinnant
+    let days = now / SECONDS_PER_DAY;
+    let z = days + 719_468;
+    let day_of_era = z % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_position = (5 * day_of_year + 2) / 153;
+    let day_of_month = day_of_year - (153 * month_position + 2) / 5;
+    (days - day_of_month) * SECONDS_PER_DAY
+}
+
+/// Player profiles which outlive individual games
+#[derive(Default)]
+pub(crate) struct Profiles {
+    profiles: HashMap<ProfileId, Profile>,
+    /// Scale rating changes by the margin of captured letters
+    pub(crate) margin_multiplier: bool,
+}
+
+impl Profiles {
+    pub(crate) fn create(&mut self, data: ProfileData) -> Result<Profile> {
+        let mut rng = rand::thread_rng();
+        let id = loop {
+            let id = (&mut rng)
+                .sample_iter(Alphanumeric)
+                .take(PROFILE_ID_LENGTH)
+                .map(char::from)
+                .collect::<String>();
+            if !self.profiles.contains_key(&id) {
+                break id;
+            }
+        };
+        let profile = Profile {
+            id: id.clone(),
+            name: data.name,
+            stats: Stats::default(),
+            rating: INITIAL_RATING,
+            rating_history: Vec::new(),
+        };
+        self.profiles.insert(id, profile.clone());
+        Ok(profile)
+    }
+
+    /// Every profile, in no particular order
+    pub(crate) fn all(&self) -> impl Iterator<Item = &Profile> {
+        self.profiles.values()
+    }
+
+    /// Put back a profile saved by `all`, replacing any with the same id
+    pub(crate) fn restore(&mut self, profile: Profile) {
+        self.profiles.insert(profile.id.clone(), profile);
+    }
+
+    pub(crate) fn get(&self, id: &str) -> Result<&Profile> {
+        self.profiles.get(id).ok_or(Error::ProfileNotFound)
+    }
+
+    pub(crate) fn record(&mut self, id: &str, result: &GameResult) {
+        if let Some(profile) = self.profiles.get_mut(id) {
+            profile.stats.record(result);
+        }
+    }
+
+    /// Update the ratings of two players after a game. `score` is `1.0` if `one` won, `0.0` if `two`
+    /// won and `0.5` for a draw. `margin` is the difference in captured letters.
+    pub(crate) fn rate(&mut self, one: &str, two: &str, score: f64, margin: usize, at: u64) {
+        if one == two {
+            return;
+        }
+        let (rating_one, rating_two) = match (self.profiles.get(one), self.profiles.get(two)) {
+            (Some(one), Some(two)) => (one.rating, two.rating),
+            _ => return,
+        };
+        let expected = 1.0 / (1.0 + 10f64.powf((rating_two - rating_one) / 400.0));
+        let multiplier = if self.margin_multiplier {
+            (margin as f64 + 1.0).ln() + 1.0
+        } else {
+            1.0
+        };
+        let change = K_FACTOR * multiplier * (score - expected);
+        for (id, change) in [(one, change), (two, -change)] {
+            if let Some(profile) = self.profiles.get_mut(id) {
+                profile.rating += change;
+                profile.rating_history.push(RatingChange { at, change });
+            }
+        }
+    }
+
+    /// Rank the profiles over `window` as of `now`, returning the requested page
+    pub(crate) fn leaderboard(&self, window: Window, page: usize, per_page: usize, now: u64) -> Leaderboard {
+        let page = page.max(1);
+        let per_page = per_page.clamp(1, MAX_PER_PAGE);
+        let mut ranked = self
+            .profiles
+            .values()
+            .filter_map(|profile| {
+                let (change, games) = match window {
+                    Window::AllTime => profile.change_since(0),
+                    Window::Month => profile.change_since(month_start(now)),
+                };
+                let score = match window {
+                    Window::AllTime => profile.rating,
+                    Window::Month => change,
+                };
+                (games > 0).then_some((score, profile, change, games))
+            })
+            .collect::<Vec<_>>();
+        ranked.sort_by(|(a, a_profile, ..), (b, b_profile, ..)| {
+            b.total_cmp(a).then_with(|| a_profile.id.cmp(&b_profile.id))
+        });
+        let total = ranked.len();
+        let entries = ranked
+            .into_iter()
+            .enumerate()
+            .skip((page - 1) * per_page)
+            .take(per_page)
+            .map(|(index, (_, profile, change, games))| LeaderboardEntry {
+                rank: index + 1,
+                name: profile.name.clone(),
+                rating: profile.rating,
+                change,
+                games,
+            })
+            .collect();
+        Leaderboard {
+            window,
+            page,
+            per_page,
+            total,
+            entries,
+        }
+    }
+}
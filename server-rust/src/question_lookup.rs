@@ -2,12 +2,15 @@ use rand::seq::SliceRandom;
 use serde::Deserialize;
 use serde::Serialize;
 use std::{
+    collections::{hash_map::Entry, HashMap},
     fs::File,
     io::{self, BufRead, BufReader},
     path::Path,
 };
 
 const DEFAULT_QUESTION: &str = "Answer the question you would have liked to be asked?";
+/// The longest question a pack should contain, in characters
+const MAX_QUESTION_LENGTH: usize = 200;
 
 #[derive(Debug, Deserialize, Serialize)]
 struct Choice {
@@ -55,6 +58,46 @@ impl QuestionLookup {
         Ok(())
     }
 
+    /// The number of questions in the bank
+    pub(crate) fn len(&self) -> usize {
+        self.questions.len()
+    }
+
+    /// Each question, in the order they were loaded
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &String> {
+        self.questions.iter()
+    }
+
+    /// Everything wrong with the loaded pack, each prefixed with its line number
+    pub(crate) fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.questions.is_empty() {
+            problems.push(String::from("the pack has no questions"));
+        }
+        let mut seen = HashMap::new();
+        for (index, question) in self.questions.iter().enumerate() {
+            let line = index + 1;
+            let trimmed = question.trim();
+            if trimmed.is_empty() {
+                problems.push(format!("line {}: blank question", line));
+                continue;
+            }
+            if trimmed != question {
+                problems.push(format!("line {}: leading or trailing whitespace", line));
+            }
+            if trimmed.chars().count() > MAX_QUESTION_LENGTH {
+                problems.push(format!("line {}: longer than {} characters", line, MAX_QUESTION_LENGTH));
+            }
+            match seen.entry(trimmed.to_lowercase()) {
+                Entry::Occupied(first) => problems.push(format!("line {}: duplicate of line {}", line, first.get())),
+                Entry::Vacant(entry) => {
+                    entry.insert(line);
+                }
+            }
+        }
+        problems
+    }
+
     pub(crate) fn get(&self) -> String {
         let mut rng = rand::thread_rng();
         self.questions
@@ -0,0 +1,180 @@
+use parking_lot::Mutex;
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::{uri::Origin, Method},
+    Data, Request,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+/// How many requests a client may make per window by default
+pub(crate) const DEFAULT_IP_LIMIT: u32 = 300;
+/// How many changes may be made to a single game per window by default
+pub(crate) const DEFAULT_GAME_LIMIT: u32 = 60;
+/// How long a rate limit window lasts by default, in seconds
+pub(crate) const DEFAULT_WINDOW: u64 = 60;
+
+/// The route limited requests are rewritten to
+const LIMITED_PATH: &str = "/api/v1/rate_limited";
+/// Health probes are never limited
+const HEALTH_PATH: &str = "/api/v1/health/";
+/// How many windows to track before forgetting the expired ones
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// A response telling the client to wait before retrying, in seconds
+pub(crate) struct RateLimited(pub(crate) u64);
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Limits {
+    /// Requests allowed from each IP address per window, `0` for no limit
+    pub(crate) per_ip: u32,
+    /// Changes each IP address may make to a game per window, `0` for no limit
+    pub(crate) per_game: u32,
+    /// The length of a window, in seconds
+    pub(crate) window: u64,
+    /// Take the client's address from the `X-Real-IP` header, which clients can set to anything
+    /// unless a reverse proxy in front of the server overwrites it
+    pub(crate) trust_proxy: bool,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            per_ip: DEFAULT_IP_LIMIT,
+            per_game: DEFAULT_GAME_LIMIT,
+            window: DEFAULT_WINDOW,
+            trust_proxy: false,
+        }
+    }
+}
+
+struct Window {
+    start: Instant,
+    requests: u32,
+}
+
+/// Fixed window request counts for each key
+struct Counter<K> {
+    windows: Mutex<HashMap<K, Window>>,
+}
+
+impl<K: Hash + Eq> Counter<K> {
+    fn new() -> Self {
+        Self {
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count a request for `key`, returning how long to wait if it is over `limit`
+    fn hit(&self, key: K, limit: u32, length: Duration) -> Option<Duration> {
+        if limit == 0 {
+            return None;
+        }
+        let now = Instant::now();
+        let mut windows = self.windows.lock();
+        if windows.len() >= PRUNE_THRESHOLD {
+            windows.retain(|_, window| now.duration_since(window.start) < length);
+        }
+        let window = windows.entry(key).or_insert(Window {
+            start: now,
+            requests: 0,
+        });
+        if now.duration_since(window.start) >= length {
+            *window = Window {
+                start: now,
+                requests: 0,
+            };
+        }
+        if window.requests >= limit {
+            return Some(length - now.duration_since(window.start));
+        }
+        window.requests += 1;
+        None
+    }
+}
+
+/// Rewrites API requests over their per-IP or per-game limit to a route answering 429
+pub(crate) struct RateLimiter {
+    limits: Limits,
+    ips: Counter<IpAddr>,
+    /// Counted per client too so one client cannot use up the limit of a game others are playing
+    games: Counter<(String, Option<IpAddr>)>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            ips: Counter::new(),
+            games: Counter::new(),
+        }
+    }
+
+    /// The address of the client making `request`
+    fn client_ip(&self, request: &Request) -> Option<IpAddr> {
+        if self.limits.trust_proxy {
+            if let Some(ip) = request.real_ip() {
+                return Some(ip);
+            }
+        }
+        request.remote().map(|remote| remote.ip())
+    }
+
+    /// How long the client must wait before `request` would be allowed
+    fn check(&self, request: &Request) -> Option<Duration> {
+        let Limits {
+            per_ip,
+            per_game,
+            window,
+            ..
+        } = self.limits;
+        let window = Duration::from_secs(window);
+        let ip = self.client_ip(request);
+        if let Some(ip) = ip {
+            if let Some(retry_after) = self.ips.hit(ip, per_ip, window) {
+                return Some(retry_after);
+            }
+        }
+        // Only changes count towards a game's limit so players can keep polling
+        if request.method() == Method::Get {
+            return None;
+        }
+        let game_id = request
+            .uri()
+            .path()
+            .strip_prefix("/api/v1/game/")?
+            .split('/')
+            .next()?;
+        self.games.hit((game_id.to_string(), ip), per_game, window)
+    }
+}
+
+impl Fairing for RateLimiter {
+    fn info(&self) -> Info {
+        Info {
+            name: "Rate Limiter",
+            kind: Kind::Request,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        let path = request.uri().path();
+        // Health probes must keep working for orchestrators however busy the server is
+        if !path.starts_with("/api/v1/") || path.starts_with(HEALTH_PATH) {
+            return;
+        }
+        if let Some(retry_after) = self.check(request) {
+            // Round up so clients never retry early
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            let uri = format!("{}/{}", LIMITED_PATH, seconds);
+            request.set_method(Method::Get);
+            request.set_uri(Origin::parse_owned(uri).expect("to parse rate limited uri"));
+        }
+    }
+}
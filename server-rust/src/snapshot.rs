@@ -0,0 +1,193 @@
+use crate::{
+    export::GameExport,
+    filter::Flag,
+    logging,
+    profiles::Profile,
+    types::{now, Error, Games, MAX_FLAGS},
+};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::{uri::Origin, Method},
+    Data, Outcome, Request, State,
+};
+use serde::{Deserialize, Serialize};
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    iterator::Signals,
+};
+use std::{
+    error, fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+/// The version of the snapshot format, bumped whenever `Snapshot` changes incompatibly
+pub(crate) const SNAPSHOT_VERSION: u32 = 1;
+
+/// How long requests in flight are given to finish once shutdown starts
+const DRAIN_PERIOD: Duration = Duration::from_secs(1);
+/// The route requests refused during shutdown are rewritten to
+const SHUTTING_DOWN_PATH: &str = "/api/v1/shutting_down";
+
+#[derive(Debug)]
+pub(crate) enum SnapshotError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+    UnsupportedVersion(u32),
+    /// A game in the snapshot could not be restored
+    Game(String, Error),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Parse(e) => write!(f, "invalid snapshot, err: {}", e),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported snapshot version {}", version),
+            Self::Game(game_id, e) => write!(f, "failed to restore game {:?}, err: {}", game_id, e),
+        }
+    }
+}
+
+impl error::Error for SnapshotError {}
+
+/// Everything the server holds in memory, written on shutdown and restored on start
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct Snapshot {
+    pub(crate) version: u32,
+    /// When the snapshot was taken in seconds since the Unix epoch
+    pub(crate) taken_at: u64,
+    pub(crate) games: Vec<GameExport>,
+    pub(crate) profiles: Vec<Profile>,
+    /// Flags waiting for review, missing from snapshots taken before they were saved
+    #[serde(default)]
+    pub(crate) flags: Vec<Flag>,
+}
+
+impl Snapshot {
+    pub(crate) fn new(games: Vec<GameExport>, profiles: Vec<Profile>) -> Self {
+        Self {
+            version: SNAPSHOT_VERSION,
+            taken_at: now(),
+            games,
+            profiles,
+            flags: Vec::new(),
+        }
+    }
+
+    pub(crate) fn take(games: &Games) -> Self {
+        let mut snapshot = Self::new(games.export_all(), games.profiles.lock().all().cloned().collect());
+        snapshot.flags = games.flags.lock().iter().cloned().collect();
+        snapshot
+    }
+
+    /// Write the snapshot to a temporary file then move it over `path` so a crash never leaves half a
+    /// snapshot behind
+    pub(crate) fn write(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_vec(self).expect("to Snapshot serialize");
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        fs::write(&temporary, json)?;
+        fs::rename(&temporary, path)
+    }
+
+    /// Read the snapshot at `path`, `None` if there is none
+    pub(crate) fn read(path: &Path) -> Result<Option<Self>, SnapshotError> {
+        let json = match fs::read(path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(SnapshotError::Io(e)),
+        };
+        let snapshot = serde_json::from_slice::<Self>(&json).map_err(SnapshotError::Parse)?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(snapshot.version));
+        }
+        Ok(Some(snapshot))
+    }
+
+    pub(crate) fn game(&self, game_id: &str) -> Option<&GameExport> {
+        self.games.iter().find(|export| export.game_id == game_id)
+    }
+
+    /// Drop finished games created before `cutoff`, and unfinished ones too if `unfinished`, returning
+    /// the number dropped
+    pub(crate) fn compact(&mut self, cutoff: u64, unfinished: bool) -> usize {
+        let before = self.games.len();
+        self.games
+            .retain(|export| export.game.created_at >= cutoff || !(export.game.finished || unfinished));
+        before - self.games.len()
+    }
+
+    /// Put every game, profile and flag back, returning the number of games restored
+    pub(crate) fn restore(self, games: &Games) -> Result<usize, SnapshotError> {
+        let restored = self.games.len();
+        let mut profiles = games.profiles.lock();
+        for profile in self.profiles {
+            profiles.restore(profile);
+        }
+        drop(profiles);
+        let mut flags = games.flags.lock();
+        flags.extend(self.flags);
+        let excess = flags.len().saturating_sub(MAX_FLAGS);
+        flags.drain(..excess);
+        drop(flags);
+        for export in self.games {
+            let game_id = export.game_id.clone();
+            games.import(export).map_err(|e| SnapshotError::Game(game_id, e))?;
+        }
+        Ok(restored)
+    }
+}
+
+/// Refuses every request which could change a game or profile once shutdown starts, so nothing is
+/// changed after the snapshot is taken
+pub(crate) struct Drain;
+
+impl Fairing for Drain {
+    fn info(&self) -> Info {
+        Info {
+            name: "Drain",
+            kind: Kind::Request,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        if matches!(request.method(), Method::Get | Method::Head | Method::Options) {
+            return;
+        }
+        if let Outcome::Success(games) = request.guard::<State<Arc<Games>>>() {
+            if games.is_shutting_down() {
+                request.set_method(Method::Get);
+                request.set_uri(Origin::parse(SHUTTING_DOWN_PATH).expect("to parse shutting down uri"));
+            }
+        }
+    }
+}
+
+/// On SIGTERM or SIGINT stop creating games, release waiting clients, save every game to `path` if
+/// given and exit
+pub(crate) fn handle_signals(games: Arc<Games>, path: Option<PathBuf>) -> io::Result<()> {
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+    thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            logging::event("shutting_down", &[("signal", &signal)]);
+            games.shut_down();
+            thread::sleep(DRAIN_PERIOD);
+            let mut code = 0;
+            if let Some(path) = path {
+                let snapshot = Snapshot::take(&games);
+                match snapshot.write(&path) {
+                    Ok(()) => logging::event("snapshot_written", &[("games", &snapshot.games.len())]),
+                    Err(e) => {
+                        eprintln!("Failed to write snapshot {:?}, err: {}", path, e);
+                        code = 1;
+                    }
+                }
+            }
+            std::process::exit(code);
+        }
+    });
+    Ok(())
+}
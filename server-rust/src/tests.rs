@@ -1,7 +1,40 @@
-use std::collections::HashMap;
+use parking_lot::Mutex;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::Arc,
+    time::Duration,
+};
 
-use crate::{rocket, types::PlayerData, Answer, Game, };
-use rocket::{http::Status, local::Client};
+use crate::{
+    admin::Notice,
+    bot::{Bot, Difficulty},
+    cli::{describe, simulate, Command, StorageCommand},
+    config::Config,
+    export::{GameExport, SCHEMA_VERSION},
+    filter::{ContentFilter, Field, FilterMode, Flag},
+    game_code,
+    logging::{self, LogFormat},
+    profiles::{month_start, Leaderboard, Profile, ProfileData, Profiles, Window},
+    matchmaking::{Fallback, MatchStatus, Matchmaking, QueueData},
+    metrics::Metrics,
+    rocket,
+    snapshot::{Snapshot, SnapshotError},
+    validation::InputLimits,
+    types::{
+        Board, BoardDelta, CreateGameData, CreatedGame, Error, GameSummary, Games, Handicap, Hint, LobbyGame,
+        Notifier, PlayerData, Rules, Series, Side, MAX_FLAGS,
+    },
+    question_lookup::QuestionLookup,
+    word_lookup::WordLookup,
+    types::Game,
+    Answer, Cli, Opt,
+};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use rocket::{
+    http::{ContentType, Header, Method, Status},
+    local::Client,
+};
+use structopt::StructOpt;
 
 #[test]
 fn not_found() {
@@ -17,6 +50,115 @@ fn heartbeat() {
     assert_eq!(res.status(), Status::Ok);
 }
 
+#[test]
+fn health() {
+    let client = Client::new(rocket(None)).unwrap();
+    let mut res = client.get("/api/v1/health/live").dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    assert_eq!(res.body_string().unwrap(), r#"{"status":"ok"}"#);
+    // Without a question file the built-in question is used so the server is ready
+    let mut res = client.get("/api/v1/health/ready").dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let readiness: serde_json::Value = serde_json::from_str(&res.body_string().unwrap()).unwrap();
+    assert_eq!(readiness["status"], "ready");
+    assert_eq!(readiness["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(readiness["checks"]["questions"]["questions"], 0);
+    assert_eq!(readiness["checks"]["storage"]["backend"], "memory");
+    assert_eq!(readiness["checks"]["storage"]["games"], 0);
+
+    // A configured question file that loaded nothing is not ready
+    let path = std::env::temp_dir().join(format!("weighty-questions-{}.txt", std::process::id()));
+    std::fs::write(&path, "").unwrap();
+    let mut config = Config::default();
+    config.questions.file = Some(path.clone());
+    let client = Client::new(rocket(Some(config))).unwrap();
+    let mut res = client.get("/api/v1/health/ready").dispatch();
+    assert_eq!(res.status(), Status::ServiceUnavailable);
+    let readiness: serde_json::Value = serde_json::from_str(&res.body_string().unwrap()).unwrap();
+    assert_eq!(readiness["status"], "unavailable");
+    assert_eq!(readiness["checks"]["questions"]["ok"], false);
+    assert_eq!(readiness["checks"]["storage"]["ok"], true);
+    std::fs::remove_file(&path).unwrap();
+
+    // Probes are never rate limited
+    let mut config = Config::default();
+    config.rate_limits.per_ip = 1;
+    let client = Client::new(rocket(Some(config))).unwrap();
+    for _ in 0..3 {
+        let res = client.get("/api/v1/health/live").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+    }
+}
+
+#[test]
+fn graceful_shutdown() {
+    let client = Client::new(rocket(None)).unwrap();
+    client
+        .put("/api/v1/game/my_game")
+        .body(serde_json::to_string(&PlayerData::new("p1")).unwrap())
+        .dispatch();
+    let mut res = client
+        .post("/api/v1/profile")
+        .body(r#"{"name":"p1"}"#)
+        .dispatch();
+    let profile = serde_json::from_str::<Profile>(&res.body_string().unwrap()).unwrap();
+    let games = client.rocket().state::<Arc<Games>>().unwrap();
+    // Shutting down releases long polls, tells clients and refuses new games
+    std::thread::scope(|scope| {
+        let poll = scope.spawn(|| {
+            let res = client.get("/api/v1/game/my_game?since=1").dispatch();
+            (res.status(), res.headers().get_one("X-Shutting-Down").map(String::from))
+        });
+        std::thread::sleep(Duration::from_millis(100));
+        games.shut_down();
+        assert_eq!(poll.join().unwrap(), (Status::Ok, Some(String::from("true"))));
+    });
+    let res = client
+        .put("/api/v1/game/other_game")
+        .body(serde_json::to_string(&PlayerData::new("p1")).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::ServiceUnavailable);
+    let res = client.get("/api/v1/health/ready").dispatch();
+    assert_eq!(res.status(), Status::ServiceUnavailable);
+    // Nothing changes once the snapshot could have been taken, though games can still be read
+    let mut res = client
+        .post("/api/v1/game/my_game")
+        .body(serde_json::to_string(&PlayerData::new("p2")).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::ServiceUnavailable);
+    assert!(res.body_string().unwrap().contains("ShuttingDown"));
+    let res = client.post("/api/v1/profile").body(r#"{"name":"p2"}"#).dispatch();
+    assert_eq!(res.status(), Status::ServiceUnavailable);
+    let mut res = client.get("/api/v1/game/my_game").dispatch();
+    let game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(game.player_two, None);
+
+    // The snapshot is restored on the next start
+    let path = std::env::temp_dir().join(format!("weighty-snapshot-{}.json", std::process::id()));
+    Snapshot::take(games).write(&path).unwrap();
+    let mut config = Config::default();
+    config.storage.snapshot = Some(path.clone());
+    let client = Client::new(rocket(Some(config))).unwrap();
+    let mut res = client.get("/api/v1/game/my_game").dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(game.player_one, Some("p1".to_string()));
+    assert_eq!(game.version, 1);
+    let res = client.get(format!("/api/v1/profile/{}", profile.id)).dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let res = client
+        .put("/api/v1/game/other_game")
+        .body(serde_json::to_string(&PlayerData::new("p1")).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+
+    // Snapshots from another version of the server are refused
+    std::fs::write(&path, r#"{"version":0,"taken_at":0,"games":[],"profiles":[]}"#).unwrap();
+    assert!(matches!(Snapshot::read(&path), Err(SnapshotError::UnsupportedVersion(0))));
+    std::fs::remove_file(&path).unwrap();
+    assert!(Snapshot::read(&path).unwrap().is_none());
+}
+
 #[test]
 fn simple_game() {
     let client = Client::new(rocket(None)).unwrap();
@@ -50,10 +192,1976 @@ fn simple_game() {
     assert_eq!(res.status(), Status::Ok);
     // Get the state of the game
     let mut res = client.get("/api/v1/game/my_game").dispatch();
-    let game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+    let mut game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
     assert_eq!(game.rounds.len(), 2);
     assert_eq!(game.previous_round().unwrap().player_one_answer, Some("sssss".to_string()));
     assert_eq!(game.previous_round().unwrap().player_two_answer, Some("test".to_string()));
+    // Keep the snapshot independent of when the test runs
+    game.created_at = 0;
+    game.updated_at = 0;
     insta::assert_debug_snapshot!(game);
+}
+
+#[test]
+fn create_game_with_rules() {
+    let client = Client::new(rocket(None)).unwrap();
+    let rules = Rules {
+        neutral_vowels: true,
+        bonus_letters: BTreeSet::from(['q', 'z']),
+        locked_letter: Some('s'),
+        unlock_round: 3,
+        steal: true,
+        ..Rules::default()
+    };
+    let data = CreateGameData::new("p1", rules.clone());
+    let res = client
+        .put("/api/v1/game/my_game")
+        .body(serde_json::to_string(&data).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let mut res = client.get("/api/v1/game/my_game").dispatch();
+    let game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(game.board.rules, rules);
+    // Rules must only reference letters on the board
+    let rules = Rules {
+        locked_letter: Some('a'),
+        ..Rules::default()
+    };
+    let data = CreateGameData::new("p1", rules);
+    let res = client
+        .put("/api/v1/game/other_game")
+        .body(serde_json::to_string(&data).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+}
+
+#[test]
+fn board_rule_variants() {
+    // Bonus letters move two steps
+    let mut board = Board::new(Rules {
+        bonus_letters: BTreeSet::from(['q']),
+        ..Rules::default()
+    });
+    board.move_board(1, String::from("qq"), String::new());
+    assert_eq!(board.board[&'q'], 4);
+    assert!(board.player_one_captured.contains(&'q'));
+
+    // Vowels push back the letter the opponent leads on most
+    let mut board = Board::new(Rules {
+        neutral_vowels: true,
+        ..Rules::default()
+    });
+    board.move_board(1, String::new(), String::from("ttd"));
+    board.move_board(2, String::from("a"), String::new());
+    assert_eq!(board.board[&'t'], -1);
+    assert_eq!(board.board[&'d'], -1);
+
+    // The locked letter is not captured until the unlock round
+    let mut board = Board::new(Rules {
+        locked_letter: Some('s'),
+        unlock_round: 2,
+        ..Rules::default()
+    });
+    board.move_board(1, String::from("ssss"), String::new());
+    assert!(board.player_one_captured.is_empty());
+    board.move_board(2, String::new(), String::new());
+    assert!(board.player_one_captured.contains(&'s'));
+}
+
+#[test]
+fn recapture_rules() {
+    // Captured letters are frozen without the steal rule
+    let mut board = Board::new(Rules::default());
+    board.move_board(1, String::from("sss"), String::new());
+    board.move_board(2, String::new(), String::from("ssssss"));
+    assert!(board.player_one_captured.contains(&'s'));
+    assert_eq!(board.board[&'s'], 3);
+
+    // The opponent can steal a captured letter by pushing it past their threshold
+    let mut board = Board::new(Rules {
+        steal: true,
+        ..Rules::default()
+    });
+    board.move_board(1, String::from("sss"), String::new());
+    // The owner cannot push a captured letter any further
+    board.move_board(2, String::from("ss"), String::new());
+    assert_eq!(board.board[&'s'], 3);
+    let deltas = board.move_board(3, String::new(), String::from("ssssss"));
+    assert_eq!(
+        deltas,
+        vec![
+            BoardDelta::Moved {
+                letter: 's',
+                from: 3,
+                to: -3
+            },
+            BoardDelta::Stolen {
+                letter: 's',
+                side: Side::PlayerTwo
+            },
+        ]
+    );
+    assert!(board.player_one_captured.is_empty());
+    assert!(board.player_two_captured.contains(&'s'));
+
+    // A side can give up a captured letter to stop the opponent capturing another
+    let mut board = Board::new(Rules {
+        sacrifice: true,
+        ..Rules::default()
+    });
+    board.move_board(1, String::new(), String::from("ttt"));
+    let deltas = board.move_board(2, String::from("sss"), String::new());
+    // Both letters' moves are recorded so replaying the moves rebuilds the board
+    assert_eq!(
+        deltas,
+        vec![
+            BoardDelta::Moved {
+                letter: 's',
+                from: 0,
+                to: 3
+            },
+            BoardDelta::Moved {
+                letter: 't',
+                from: -3,
+                to: 0
+            },
+            BoardDelta::Moved {
+                letter: 's',
+                from: 3,
+                to: 2
+            },
+            BoardDelta::Sacrificed {
+                letter: 't',
+                side: Side::PlayerTwo,
+                saved: 's'
+            },
+        ]
+    );
+    assert!(board.player_one_captured.is_empty());
+    assert!(board.player_two_captured.is_empty());
+    assert_eq!(board.board[&'s'], 2);
+    assert_eq!(board.board[&'t'], 0);
+}
+
+#[test]
+fn handicap_rules() {
+    let mut board = Board::new(Rules {
+        player_one_handicap: Handicap {
+            capture_threshold: 2,
+            offsets: BTreeMap::from([('s', 1)]),
+            captured: BTreeSet::from(['t']),
+        },
+        player_two_handicap: Handicap {
+            capture_threshold: 4,
+            ..Handicap::default()
+        },
+        ..Rules::default()
+    });
+    assert!(board.rules.validate().is_ok());
+    assert_eq!(board.board[&'s'], 1);
+    assert_eq!(board.board[&'t'], 3);
+    assert!(board.player_one_captured.contains(&'t'));
+    // Player one captures with a lower threshold and a head start
+    board.move_board(1, String::from("ssr"), String::new());
+    assert!(board.player_one_captured.contains(&'s'));
+    assert!(!board.player_one_captured.contains(&'r'));
+    // Player two needs to push further
+    board.move_board(2, String::new(), String::from("dddd"));
+    assert!(board.player_two_captured.is_empty());
+    board.move_board(3, String::new(), String::from("d"));
+    assert!(board.player_two_captured.contains(&'d'));
+
+    // The same letter cannot start captured by both sides
+    let client = Client::new(rocket(None)).unwrap();
+    let handicap = Handicap {
+        captured: BTreeSet::from(['t']),
+        ..Handicap::default()
+    };
+    let rules = Rules {
+        player_one_handicap: handicap.clone(),
+        player_two_handicap: handicap,
+        ..Rules::default()
+    };
+    let res = client
+        .put("/api/v1/game/my_game")
+        .body(serde_json::to_string(&CreateGameData::new("p1", rules)).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+
+    // No letter may start at or past a capture threshold, alone or with the other side's offset
+    let offsets = |offsets: &[(char, i32)], capture_threshold| Handicap {
+        capture_threshold,
+        offsets: offsets.iter().copied().collect(),
+        ..Handicap::default()
+    };
+    for (one, two, valid) in [
+        (offsets(&[('s', 1)], 2), offsets(&[], 2), true),
+        (offsets(&[('s', 2)], 2), offsets(&[], 2), false),
+        (offsets(&[('s', -2)], 2), offsets(&[], 2), false),
+        (offsets(&[('s', 1)], 2), offsets(&[('s', -1)], 3), false),
+        (offsets(&[('s', -1)], 2), offsets(&[('s', 1)], 3), true),
+        (offsets(&[('s', -1)], 2), offsets(&[], 1), false),
+    ] {
+        let rules = Rules {
+            player_one_handicap: one,
+            player_two_handicap: two,
+            ..Rules::default()
+        };
+        assert_eq!(rules.validate().is_ok(), valid, "{:?}", rules);
+    }
+    // Extreme values are refused rather than overflowing
+    for handicaps in [
+        r#""player_one_handicap":{"capture_threshold":2,"offsets":{"s":-2147483648}}"#,
+        r#""player_one_handicap":{"capture_threshold":2147483647,"offsets":{"s":2147483646}},
+            "player_two_handicap":{"capture_threshold":2147483647,"offsets":{"s":-2147483646}}"#,
+        r#""player_one_handicap":{"capture_threshold":2147483647,"captured":["t"]}"#,
+        r#""player_one_handicap":{"capture_threshold":101}"#,
+    ] {
+        let body = format!(r#"{{"player":"p1","rules":{{{}}}}}"#, handicaps);
+        let res = client.put("/api/v1/game/extreme").body(body).dispatch();
+        assert_eq!(res.status(), Status::BadRequest, "{}", handicaps);
+    }
+}
+
+#[test]
+fn bot_game() {
+    let client = Client::new(rocket(None)).unwrap();
+    let p = PlayerData::new("p1");
+    let res = client
+        .put("/api/v1/game/my_game")
+        .body(serde_json::to_string(&p).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    // The bot takes the second seat and answers straight away
+    let res = client
+        .post("/api/v1/game/my_game/bot")
+        .body(r#"{"difficulty": "greedy"}"#)
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let mut res = client.get("/api/v1/game/my_game").dispatch();
+    let game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(game.player_two, Some(String::from("Bot")));
+    assert_eq!(game.bot, Some(Bot::new(Difficulty::Greedy)));
+    assert!(game.current_round().player_two_answer.is_some());
+    // Only one bot per game
+    let res = client.post("/api/v1/game/my_game/bot").body("{}").dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+    // The bot answers the next round as soon as it starts
+    let a = Answer::new("p1", "sss");
+    let res = client
+        .post("/api/v1/game/my_game/answer")
+        .body(serde_json::to_string(&a).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let mut res = client.get("/api/v1/game/my_game").dispatch();
+    let game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(game.rounds.len(), 2);
+    assert!(game.current_round().player_two_answer.is_some());
+    assert!(game.current_round().player_one_answer.is_none());
+}
+
+#[test]
+fn bot_choices() {
+    let mut words = WordLookup::default();
+    words.insert("Letters", ["aaa", "sst", "sss"]);
+    let words = words.get("letters");
+    let board = Board::new(Rules::default());
+    // A greedy bot takes the capture
+    let bot = Bot::new(Difficulty::Greedy);
+    assert_eq!(bot.choose(&board, 1, Side::PlayerTwo, words), "sss");
+    // Every bot answers from the word list
+    for difficulty in [Difficulty::Random, Difficulty::Normal] {
+        let answer = Bot::new(difficulty).choose(&board, 1, Side::PlayerOne, words);
+        assert!(words.contains(&answer));
+    }
+    // Unknown categories fall back to every known word
+    let mut lookup = WordLookup::default();
+    lookup.insert("Letters", ["sss"]);
+    assert_eq!(lookup.get("Numbers"), ["sss"]);
+}
+
+#[test]
+fn hints() {
+    let mut words = WordLookup::default();
+    words.insert("Letters", ["aaa", "sst", "sss"]);
+    let games = Games::default();
+    let rules = Rules {
+        max_hints: 1,
+        ..Rules::default()
+    };
+    let question = String::from("Letters");
+    games
+        .create(String::from("my_game"), CreateGameData::new("p1", rules), question.clone(), question)
+        .unwrap();
+    let game = games.get("my_game").unwrap();
+    let mut game = game.lock();
+    game.add_player(String::from("p2")).unwrap();
+    let hints = game.hint(&String::from("p1"), &words).unwrap();
+    assert_eq!(
+        hints[0],
+        Hint {
+            answer: String::from("sss"),
+            gain: 10,
+            captures: vec!['s'],
+        }
+    );
+    assert_eq!(hints.len(), 3);
+    assert_eq!(hints[2].answer, "aaa");
+    assert_eq!(game.hints_used[&String::from("p1")], 1);
+    // Hints are limited per player
+    assert!(game.hint(&String::from("p1"), &words).is_err());
+    assert!(game.hint(&String::from("p2"), &words).is_ok());
+
+    let client = Client::new(rocket(None)).unwrap();
+    let p = PlayerData::new("p1");
+    client
+        .put("/api/v1/game/my_game")
+        .body(serde_json::to_string(&p).unwrap())
+        .dispatch();
+    let mut res = client
+        .post("/api/v1/game/my_game/hint")
+        .body(serde_json::to_string(&p).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    // The bundled words are used when no words file is given
+    let hints = serde_json::from_str::<Vec<Hint>>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(hints.len(), 3);
+    let res = client
+        .post("/api/v1/game/my_game/hint")
+        .body(serde_json::to_string(&PlayerData::new("p3")).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+}
+
+#[test]
+fn generated_game_codes() {
+    let client = Client::new(rocket(None)).unwrap();
+    let mut res = client
+        .post("/api/v1/game")
+        .body(serde_json::to_string(&PlayerData::new("p1")).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let created = serde_json::from_str::<CreatedGame>(&res.body_string().unwrap()).unwrap();
+    assert!(game_code::is_valid(&created.game_id));
+    assert_eq!(created.join_url, format!("/?game={}", created.game_id));
+    // Other players join with the generated code, typed in any case
+    let res = client
+        .post(format!("/api/v1/game/{}", created.game_id.to_lowercase()))
+        .body(serde_json::to_string(&PlayerData::new("p2")).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let mixed = created.game_id[..3].to_lowercase() + &created.game_id[3..];
+    let mut res = client.get(format!("/api/v1/game/{}?since=0", mixed)).dispatch();
+    let game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(game.player_two.as_deref(), Some("p2"));
+    // Vanity ids spelling a code are stored as the code
+    let res = client
+        .post("/api/v1/game")
+        .body(r#"{"player": "p1", "game_id": "bakemu"}"#)
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let res = client.get("/api/v1/game/BaKeMu").dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    // A vanity id is used as is but must not be taken
+    let mut res = client
+        .post("/api/v1/game")
+        .body(r#"{"player": "p1", "game_id": "my_game"}"#)
+        .dispatch();
+    let created = serde_json::from_str::<CreatedGame>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(created.game_id, "my_game");
+    let res = client
+        .post("/api/v1/game")
+        .body(r#"{"player": "p1", "game_id": "my_game"}"#)
+        .dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+
+    // Join URLs open the client at the public URL with the game filled in
+    let mut config = Config::default();
+    config.server.public_url = String::from("https://weighty.example/");
+    let client = Client::new(rocket(Some(config))).unwrap();
+    let mut res = client
+        .post("/api/v1/game")
+        .body(r#"{"player": "p1", "game_id": "my_game"}"#)
+        .dispatch();
+    let created = serde_json::from_str::<CreatedGame>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(created.join_url, "https://weighty.example/?game=my_game");
+}
+
+#[test]
+fn lobby() {
+    let client = Client::new(rocket(None)).unwrap();
+    let mut data = CreateGameData::new(
+        "p1",
+        Rules {
+            steal: true,
+            ..Rules::default()
+        },
+    );
+    data.public = true;
+    for game_id in ["public_game", "full_game"] {
+        client
+            .put(format!("/api/v1/game/{}", game_id))
+            .body(serde_json::to_string(&data).unwrap())
+            .dispatch();
+    }
+    client
+        .put("/api/v1/game/private_game")
+        .body(serde_json::to_string(&PlayerData::new("p1")).unwrap())
+        .dispatch();
+    client
+        .post("/api/v1/game/full_game")
+        .body(serde_json::to_string(&PlayerData::new("p2")).unwrap())
+        .dispatch();
+    let mut res = client.get("/api/v1/lobby").dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let lobby = serde_json::from_str::<Vec<LobbyGame>>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(lobby.len(), 1);
+    assert_eq!(lobby[0].game_id, "public_game");
+    assert_eq!(lobby[0].players, vec![String::from("p1")]);
+    assert_eq!(lobby[0].open_seats, 1);
+    assert_eq!(lobby[0].rules, vec![String::from("steal")]);
+}
+
+#[test]
+fn matchmaking_pairs_players() {
+    let client = Client::new(rocket(None)).unwrap();
+    let mut res = client
+        .post("/api/v1/matchmaking")
+        .body(serde_json::to_string(&PlayerData::new("p1")).unwrap())
+        .dispatch();
+    let status = serde_json::from_str::<MatchStatus>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(status, MatchStatus::Waiting);
+    let mut res = client.get("/api/v1/matchmaking/p1").dispatch();
+    let status = serde_json::from_str::<MatchStatus>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(status, MatchStatus::Waiting);
+    // The second player is paired with the first
+    let mut res = client
+        .post("/api/v1/matchmaking")
+        .body(serde_json::to_string(&PlayerData::new("p2")).unwrap())
+        .dispatch();
+    let status = serde_json::from_str::<MatchStatus>(&res.body_string().unwrap()).unwrap();
+    let game_id = match status {
+        MatchStatus::Matched { game_id } => game_id,
+        status => panic!("unexpected status {:?}", status),
+    };
+    let mut res = client.get("/api/v1/matchmaking/p1").dispatch();
+    let status = serde_json::from_str::<MatchStatus>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(status, MatchStatus::Matched { game_id: game_id.clone() });
+    let mut res = client.get(format!("/api/v1/game/{}", game_id)).dispatch();
+    let game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(game.player_one, Some(String::from("p1")));
+    assert_eq!(game.player_two, Some(String::from("p2")));
+    // Players who left or were never queued are not found
+    client
+        .post("/api/v1/matchmaking")
+        .body(serde_json::to_string(&PlayerData::new("p3")).unwrap())
+        .dispatch();
+    client.delete("/api/v1/matchmaking/p3").dispatch();
+    let res = client.get("/api/v1/matchmaking/p3").dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+}
+
+#[test]
+fn matchmaking_fallback() {
+    let mut matchmaking = Matchmaking::new(Duration::ZERO);
+    let games = Games::default();
+    let questions = QuestionLookup::default();
+    let words = WordLookup::default();
+    let data = QueueData {
+        player: String::from("p1"),
+        fallback: Fallback::Cancel,
+    };
+    matchmaking.join(data, &games, &questions).unwrap();
+    let status = matchmaking.poll(&String::from("p1"), &games, &questions, &words);
+    assert_eq!(status.unwrap(), MatchStatus::Cancelled);
+
+    let data = QueueData {
+        player: String::from("p1"),
+        fallback: Fallback::Bot {
+            difficulty: Difficulty::Random,
+        },
+    };
+    matchmaking.join(data, &games, &questions).unwrap();
+    let status = matchmaking.poll(&String::from("p1"), &games, &questions, &words);
+    let game_id = match status.unwrap() {
+        MatchStatus::Matched { game_id } => game_id,
+        status => panic!("unexpected status {:?}", status),
+    };
+    let game = games.get(&game_id).unwrap();
+    assert_eq!(game.lock().bot, Some(Bot::new(Difficulty::Random)));
+
+    // A player named like the bot still gets a game against it
+    let data = QueueData {
+        player: String::from("Bot"),
+        fallback: Fallback::Bot {
+            difficulty: Difficulty::Random,
+        },
+    };
+    matchmaking.join(data, &games, &questions).unwrap();
+    let status = matchmaking.poll(&String::from("Bot"), &games, &questions, &words);
+    let game_id = match status.unwrap() {
+        MatchStatus::Matched { game_id } => game_id,
+        status => panic!("unexpected status {:?}", status),
+    };
+    let game = games.get(&game_id).unwrap();
+    assert_eq!(game.lock().player_two.as_deref(), Some("Bot 2"));
+
+    // Players who have waited too long are not paired, so they get their fallback
+    for player in ["p2", "p3"] {
+        let data = QueueData {
+            player: String::from(player),
+            fallback: Fallback::Cancel,
+        };
+        let status = matchmaking.join(data, &games, &questions);
+        assert_eq!(status.unwrap(), MatchStatus::Waiting);
+    }
+    let status = matchmaking.poll(&String::from("p2"), &games, &questions, &words);
+    assert_eq!(status.unwrap(), MatchStatus::Cancelled);
+
+    // Paired players who never poll are forgotten once the timeout passes
+    let mut matchmaking = Matchmaking::new(Duration::from_millis(100));
+    for player in ["p1", "p2"] {
+        let data = QueueData {
+            player: String::from(player),
+            fallback: Fallback::Cancel,
+        };
+        matchmaking.join(data, &games, &questions).unwrap();
+    }
+    std::thread::sleep(Duration::from_millis(150));
+    let status = matchmaking.poll(&String::from("p1"), &games, &questions, &words);
+    assert!(matches!(status, Err(Error::PlayerNotFound)));
+
+    // A player whose game could not be created stays queued
+    let games = Games::default();
+    let data = QueueData {
+        player: String::from("p1"),
+        fallback: Fallback::Cancel,
+    };
+    matchmaking.join(data, &games, &questions).unwrap();
+    games.shut_down();
+    let data = QueueData {
+        player: String::from("p2"),
+        fallback: Fallback::Cancel,
+    };
+    assert!(matches!(matchmaking.join(data, &games, &questions), Err(Error::ShuttingDown)));
+    let status = matchmaking.poll(&String::from("p1"), &games, &questions, &words);
+    assert_eq!(status.unwrap(), MatchStatus::Waiting);
+    assert_eq!(games.len(), 0);
+}
+
+#[test]
+fn rematch_series() {
+    let client = Client::new(rocket(None)).unwrap();
+    let mut data = CreateGameData::new(
+        "p1",
+        Rules {
+            letters_to_win: 1,
+            ..Rules::default()
+        },
+    );
+    data.best_of = 3;
+    client
+        .put("/api/v1/game/my_game")
+        .body(serde_json::to_string(&data).unwrap())
+        .dispatch();
+    client
+        .post("/api/v1/game/my_game")
+        .body(serde_json::to_string(&PlayerData::new("p2")).unwrap())
+        .dispatch();
+    // Only finished games can be rematched
+    let res = client.post("/api/v1/game/my_game/rematch").dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+    for answer in [Answer::new("p1", "sss"), Answer::new("p2", "t")] {
+        client
+            .post("/api/v1/game/my_game/answer")
+            .body(serde_json::to_string(&answer).unwrap())
+            .dispatch();
+    }
+    let mut res = client.get("/api/v1/game/my_game").dispatch();
+    let game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+    assert!(game.finished);
+    assert_eq!(game.winner, Some(String::from("p1")));
+    assert_eq!(game.rounds.len(), 1);
+    let res = client
+        .post("/api/v1/game/my_game/answer")
+        .body(serde_json::to_string(&Answer::new("p1", "x")).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+
+    let mut res = client.post("/api/v1/game/my_game/rematch").dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let created = serde_json::from_str::<CreatedGame>(&res.body_string().unwrap()).unwrap();
+    // Asking again returns the same rematch
+    let mut res = client.post("/api/v1/game/my_game/rematch").dispatch();
+    let again = serde_json::from_str::<CreatedGame>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(again.game_id, created.game_id);
+    let mut res = client.get(format!("/api/v1/game/{}", created.game_id)).dispatch();
+    let rematch = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(rematch.player_one, Some(String::from("p2")));
+    assert_eq!(rematch.player_two, Some(String::from("p1")));
+    assert_eq!(rematch.previous_game, Some(String::from("my_game")));
+    assert_eq!(
+        rematch.series,
+        Series {
+            best_of: 3,
+            results: vec![Some(String::from("p1"))],
+            wins: BTreeMap::from([(String::from("p1"), 1)]),
+            winner: None,
+        }
+    );
+    let mut res = client.get("/api/v1/game/my_game").dispatch();
+    let game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(game.next_game, Some(created.game_id));
+}
+
+#[test]
+fn profile_stats() {
+    let client = Client::new(rocket(None)).unwrap();
+    let mut profiles = Vec::new();
+    for name in ["Alice", "Bob"] {
+        let mut res = client
+            .post("/api/v1/profile")
+            .body(format!(r#"{{"name": "{}"}}"#, name))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        profiles.push(serde_json::from_str::<Profile>(&res.body_string().unwrap()).unwrap());
+    }
+    let mut data = CreateGameData::new(
+        "p1",
+        Rules {
+            letters_to_win: 2,
+            ..Rules::default()
+        },
+    );
+    data.profile = Some(profiles[0].id.clone());
+    client
+        .put("/api/v1/game/my_game")
+        .body(serde_json::to_string(&data).unwrap())
+        .dispatch();
+    // Unknown profiles are rejected
+    let mut p = PlayerData::new("p2");
+    p.profile = Some(String::from("unknown"));
+    let res = client
+        .post("/api/v1/game/my_game")
+        .body(serde_json::to_string(&p).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+    // One profile cannot take both seats
+    p.profile = Some(profiles[0].id.clone());
+    let mut res = client
+        .post("/api/v1/game/my_game")
+        .body(serde_json::to_string(&p).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+    assert!(res.body_string().unwrap().contains("ProfileConflict"));
+    p.profile = Some(profiles[1].id.clone());
+    let res = client
+        .post("/api/v1/game/my_game")
+        .body(serde_json::to_string(&p).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    for answer in [
+        Answer::new("p1", "sssb"),
+        Answer::new("p2", "d"),
+        Answer::new("p1", "ttt"),
+        Answer::new("p2", "d"),
+    ] {
+        let res = client
+            .post("/api/v1/game/my_game/answer")
+            .body(serde_json::to_string(&answer).unwrap())
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+    }
+    let mut res = client.get(format!("/api/v1/profile/{}", profiles[0].id)).dispatch();
+    let alice = serde_json::from_str::<Profile>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(alice.name, "Alice");
+    assert_eq!(alice.stats.games_played, 1);
+    assert_eq!(alice.stats.wins, 1);
+    assert_eq!(alice.stats.letters_captured, 2);
+    assert_eq!(alice.stats.answers, 2);
+    assert_eq!(alice.stats.average_answer_length, 3.5);
+    assert_eq!(alice.stats.favorite_letters, vec!['s', 't', 'b']);
+    let mut res = client.get(format!("/api/v1/profile/{}", profiles[1].id)).dispatch();
+    let bob = serde_json::from_str::<Profile>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(bob.stats.games_played, 1);
+    assert_eq!(bob.stats.wins, 0);
+    assert_eq!(bob.stats.letters_captured, 0);
+    let res = client.get("/api/v1/profile/unknown").dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+}
+
+#[test]
+fn ratings_and_leaderboard() {
+    assert_eq!(month_start(1792423800), 1790812800);
+    assert_eq!(month_start(1709251140), 1706745600);
+
+    let mut profiles = Profiles::default();
+    let ids = ["Alice", "Bob", "Carol"]
+        .into_iter()
+        .map(|name| {
+            profiles
+                .create(ProfileData {
+                    name: String::from(name),
+                })
+                .unwrap()
+                .id
+        })
+        .collect::<Vec<_>>();
+    let now = 1792423800;
+    // Alice beats Bob last month and Carol beats Alice this month
+    profiles.rate(&ids[0], &ids[1], 1.0, 3, 1790812800 - 1);
+    profiles.rate(&ids[2], &ids[0], 1.0, 3, now);
+    assert!((profiles.get(&ids[0]).unwrap().rating - 1499.26).abs() < 0.01);
+    assert_eq!(profiles.get(&ids[1]).unwrap().rating, 1484.0);
+
+    let all_time = profiles.leaderboard(Window::AllTime, 1, 2, now);
+    assert_eq!(all_time.total, 3);
+    let names = all_time.entries.iter().map(|entry| entry.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["Carol", "Alice"]);
+    // Ids are never published as they are all it takes to play under a profile
+    let json = serde_json::to_string(&all_time).unwrap();
+    assert!(ids.iter().all(|id| !json.contains(id.as_str())), "{}", json);
+    // A profile is never rated against itself
+    profiles.rate(&ids[0], &ids[0], 1.0, 3, now);
+    assert!((profiles.get(&ids[0]).unwrap().rating - 1499.26).abs() < 0.01);
+    let page_two = profiles.leaderboard(Window::AllTime, 2, 2, now);
+    assert_eq!(page_two.entries[0].name, "Bob");
+    assert_eq!(page_two.entries[0].rank, 3);
+    // Only games within the month count for the monthly leaderboard
+    let month = profiles.leaderboard(Window::Month, 1, 20, now);
+    let names = month.entries.iter().map(|entry| entry.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["Carol", "Alice"]);
+    assert_eq!(month.entries[1].games, 1);
+
+    // The margin multiplier scales the rating change
+    profiles.margin_multiplier = true;
+    let before = profiles.get(&ids[1]).unwrap().rating;
+    profiles.rate(&ids[1], &ids[2], 0.5, 0, now);
+    let draw = profiles.get(&ids[1]).unwrap().rating - before;
+    profiles.rate(&ids[1], &ids[2], 1.0, 4, now);
+    let win = profiles.get(&ids[1]).unwrap().rating - before - draw;
+    assert!(win > 32.0);
+
+    let client = Client::new(rocket(None)).unwrap();
+    let mut res = client.get("/api/v1/leaderboard?window=month&page=1").dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let leaderboard = serde_json::from_str::<Leaderboard>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(leaderboard.window, Window::Month);
+    assert!(leaderboard.entries.is_empty());
+}
 
-}
\ No newline at end of file
+#[test]
+fn export_and_import() {
+    const TOKEN: &str = "an-admin-token-for-tests";
+    let auth = || Header::new("Authorization", format!("Bearer {}", TOKEN));
+    let mut config = Config::default();
+    config.admin.token = Some(TOKEN.to_string());
+    let client = Client::new(rocket(Some(config))).unwrap();
+    client
+        .put("/api/v1/game/my_game")
+        .body(serde_json::to_string(&PlayerData::new("p1")).unwrap())
+        .dispatch();
+    client
+        .post("/api/v1/game/my_game")
+        .body(serde_json::to_string(&PlayerData::new("p2")).unwrap())
+        .dispatch();
+    for answer in [Answer::new("p1", "sss"), Answer::new("p2", "t, \"!\"")] {
+        client
+            .post("/api/v1/game/my_game/answer")
+            .body(serde_json::to_string(&answer).unwrap())
+            .dispatch();
+    }
+    let mut res = client.get("/api/v1/game/my_game/export?format=csv").dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    assert_eq!(res.content_type(), Some(ContentType::CSV));
+    let csv = res.body_string().unwrap();
+    let lines = csv.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("round,player_one,"));
+    assert!(lines[1].ends_with(r#","t, ""!""",s+3 t-1,s,,"#));
+
+    let mut res = client.get("/api/v1/game/my_game/export").dispatch();
+    assert_eq!(res.content_type(), Some(ContentType::JSON));
+    let mut export = serde_json::from_str::<GameExport>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(export.schema_version, SCHEMA_VERSION);
+    assert_eq!(export.game_id, "my_game");
+    // Games cannot be imported over an existing game
+    let res = client
+        .post("/api/v1/import")
+        .header(auth())
+        .body(serde_json::to_string(&export).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+    client.delete("/api/v1/game/my_game").dispatch();
+    export.schema_version += 1;
+    let res = client
+        .post("/api/v1/import")
+        .header(auth())
+        .body(serde_json::to_string(&export).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+    export.schema_version = SCHEMA_VERSION;
+    // Only admins may import, and only games which could have been played
+    let res = client
+        .post("/api/v1/import")
+        .body(serde_json::to_string(&export).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::Unauthorized);
+    let mut forgeries = [export.clone(), export.clone(), export.clone(), export.clone(), export.clone()];
+    forgeries[0].game.board.player_two_captured.insert('s');
+    forgeries[1].game.board.board.insert('a', 100);
+    forgeries[2].game.finished = true;
+    forgeries[2].game.winner = Some(String::from("p3"));
+    // An uncaptured letter past a threshold, and one so far out the next move would overflow
+    let board = &export.game.board;
+    let uncaptured = *board
+        .board
+        .keys()
+        .find(|letter| !board.player_one_captured.contains(letter) && !board.player_two_captured.contains(letter))
+        .unwrap();
+    forgeries[3].game.board.board.insert(uncaptured, 3);
+    forgeries[4].game.board.board.insert(uncaptured, i32::MIN);
+    let mut board = Board::new(Rules::default());
+    board.player_two_captured.insert('s');
+    board.board.insert('s', i32::MIN);
+    assert!(matches!(board.validate(), Err(Error::InvalidExport)));
+    board.board.insert('s', -3);
+    assert!(board.validate().is_ok());
+    for forgery in &forgeries {
+        let res = client
+            .post("/api/v1/import")
+            .header(auth())
+            .body(serde_json::to_string(forgery).unwrap())
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+    }
+    let res = client
+        .post("/api/v1/import")
+        .header(auth())
+        .body(serde_json::to_string(&export).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let mut res = client.get("/api/v1/game/my_game").dispatch();
+    let game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(game.rounds.len(), 2);
+    assert_eq!(game.board.player_one_captured, BTreeSet::from(['s']));
+
+    // Exports made before games had versions still import
+    let res = client
+        .post("/api/v1/import")
+        .header(auth())
+        .body(include_str!("fixtures/export_v1.json"))
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let mut res = client.get("/api/v1/game/old_game").dispatch();
+    let game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(game.version, 0);
+    assert_eq!(game.rounds[0].player_two_answer.as_deref(), Some("zebra"));
+}
+
+#[test]
+fn etags() {
+    let client = Client::new(rocket(None)).unwrap();
+    client
+        .put("/api/v1/game/my_game")
+        .body(serde_json::to_string(&PlayerData::new("p1")).unwrap())
+        .dispatch();
+    let mut res = client.get("/api/v1/game/my_game").dispatch();
+    let etag = res.headers().get_one("ETag").unwrap().to_string();
+    let game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(etag, game.etag());
+    // Polling with the current ETag returns nothing new
+    let mut res = client
+        .get("/api/v1/game/my_game")
+        .header(Header::new("If-None-Match", etag.clone()))
+        .dispatch();
+    assert_eq!(res.status(), Status::NotModified);
+    assert_eq!(res.headers().get_one("ETag"), Some(etag.as_str()));
+    assert!(res.body_string().is_none());
+    // Changes made from the current version succeed
+    let res = client
+        .post("/api/v1/game/my_game")
+        .header(Header::new("If-Match", etag.clone()))
+        .body(serde_json::to_string(&PlayerData::new("p2")).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let mut res = client
+        .get("/api/v1/game/my_game")
+        .header(Header::new("If-None-Match", etag.clone()))
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(game.version, 2);
+    // Changes made from a stale version conflict
+    let res = client
+        .post("/api/v1/game/my_game/answer")
+        .header(Header::new("If-Match", etag))
+        .body(serde_json::to_string(&Answer::new("p1", "a")).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::Conflict);
+    let res = client
+        .post("/api/v1/game/my_game/answer")
+        .header(Header::new("If-Match", "*"))
+        .body(serde_json::to_string(&Answer::new("p1", "a")).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+}
+
+#[test]
+fn long_polling() {
+    let client = Client::new(rocket(None)).unwrap();
+    client
+        .put("/api/v1/game/my_game")
+        .body(serde_json::to_string(&PlayerData::new("p1")).unwrap())
+        .dispatch();
+    // Polling from an older version returns straight away
+    let mut res = client.get("/api/v1/game/my_game?since=0").dispatch();
+    let game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(game.version, 1);
+    // Polling from the current version waits for the next change
+    std::thread::scope(|scope| {
+        let poll = scope.spawn(|| {
+            let mut res = client.get("/api/v1/game/my_game?since=1").dispatch();
+            serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap()
+        });
+        std::thread::sleep(Duration::from_millis(100));
+        client
+            .post("/api/v1/game/my_game")
+            .body(serde_json::to_string(&PlayerData::new("p2")).unwrap())
+            .dispatch();
+        let game = poll.join().unwrap();
+        assert_eq!(game.version, 2);
+        assert_eq!(game.player_two, Some("p2".to_string()));
+    });
+    // Without a change the wait ends at the timeout
+    let start = std::time::Instant::now();
+    Notifier::default().wait(0, Duration::from_millis(50));
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    // Deleting the game wakes waiters
+    std::thread::scope(|scope| {
+        let poll = scope.spawn(|| client.get("/api/v1/game/my_game?since=2").dispatch().status());
+        std::thread::sleep(Duration::from_millis(100));
+        client.delete("/api/v1/game/my_game").dispatch();
+        assert_eq!(poll.join().unwrap(), Status::BadRequest);
+    });
+
+    // Waiting requests are capped so they cannot take every worker
+    let mut config = Config::default();
+    config.server.workers = Some(2);
+    config.server.long_poll_timeout = 5;
+    config.validate().unwrap();
+    assert_eq!(config.server.max_long_polls(), 1);
+    let client = Client::new(rocket(Some(config.clone()))).unwrap();
+    client
+        .put("/api/v1/game/my_game")
+        .body(serde_json::to_string(&PlayerData::new("p1")).unwrap())
+        .dispatch();
+    std::thread::scope(|scope| {
+        let polls = (0..2)
+            .map(|_| {
+                scope.spawn(|| {
+                    let start = std::time::Instant::now();
+                    client.get("/api/v1/game/my_game?since=1").dispatch();
+                    start.elapsed()
+                })
+            })
+            .collect::<Vec<_>>();
+        std::thread::sleep(Duration::from_millis(200));
+        // Other requests are served while a poll is parked
+        let res = client.get("/api/v1/health/ready").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        client
+            .post("/api/v1/game/my_game")
+            .body(serde_json::to_string(&PlayerData::new("p2")).unwrap())
+            .dispatch();
+        let mut waits = polls.into_iter().map(|poll| poll.join().unwrap()).collect::<Vec<_>>();
+        waits.sort();
+        // One poll responded straight away and the other waited for the change
+        assert!(waits[0] < Duration::from_millis(150));
+        assert!(waits[1] >= Duration::from_millis(150));
+        assert!(waits[1] < Duration::from_secs(5));
+    });
+
+    // Waits are kept short and must leave workers free
+    config.server.max_long_polls = Some(2);
+    assert!(config.validate().unwrap_err().to_string().contains("server.max_long_polls"));
+    config.server.max_long_polls = None;
+    config.server.long_poll_timeout = 600;
+    assert!(config.validate().unwrap_err().to_string().contains("server.long_poll_timeout"));
+}
+
+#[test]
+fn concurrent_games() {
+    const GAMES: usize = 400;
+    const THREADS: usize = 16;
+    const ROUNDS: usize = 3;
+    /// A floor far below what even a debug build manages, so only a serious slowdown fails
+    const MIN_GAMES_PER_SECOND: f64 = 20.0;
+    let client = Client::new(rocket(None)).unwrap();
+    let start = std::time::Instant::now();
+    std::thread::scope(|scope| {
+        for thread in 0..THREADS {
+            let client = &client;
+            scope.spawn(move || {
+                for game in (thread..GAMES).step_by(THREADS) {
+                    let url = format!("/api/v1/game/load_{}", game);
+                    let res = client
+                        .put(url.clone())
+                        .body(serde_json::to_string(&PlayerData::new("p1")).unwrap())
+                        .dispatch();
+                    assert_eq!(res.status(), Status::Ok);
+                    let res = client
+                        .post(url.clone())
+                        .body(serde_json::to_string(&PlayerData::new("p2")).unwrap())
+                        .dispatch();
+                    assert_eq!(res.status(), Status::Ok);
+                    for _ in 0..ROUNDS {
+                        for player in ["p1", "p2"] {
+                            let res = client
+                                .post(format!("{}/answer", url))
+                                .body(serde_json::to_string(&Answer::new(player, "abc")).unwrap())
+                                .dispatch();
+                            assert_eq!(res.status(), Status::Ok);
+                        }
+                    }
+                }
+            });
+        }
+    });
+    let games_per_second = GAMES as f64 / start.elapsed().as_secs_f64();
+    println!(
+        "played {} games of {} rounds on {} threads, {:.0} games/s ({:.0} requests/s)",
+        GAMES,
+        ROUNDS,
+        THREADS,
+        games_per_second,
+        games_per_second * (2 + 2 * ROUNDS) as f64
+    );
+    assert!(games_per_second >= MIN_GAMES_PER_SECOND, "only {:.1} games/s", games_per_second);
+    for game in 0..GAMES {
+        let mut res = client.get(format!("/api/v1/game/load_{}", game)).dispatch();
+        let game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+        assert_eq!(game.rounds.len(), ROUNDS + 1);
+    }
+
+    // A game held by one request does not hold up requests to other games
+    let games = client.rocket().state::<Arc<Games>>().unwrap();
+    let held = games.get("load_0").unwrap();
+    let held = held.lock();
+    std::thread::scope(|scope| {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        for game in ["load_0", "load_1"] {
+            let (client, sender) = (&client, sender.clone());
+            scope.spawn(move || {
+                let res = client.get(format!("/api/v1/game/{}", game)).dispatch();
+                sender.send((game, res.status())).unwrap();
+            });
+        }
+        let finished = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(finished, ("load_1", Status::Ok));
+        assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+        drop(held);
+        let finished = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(finished, ("load_0", Status::Ok));
+    });
+}
+
+#[test]
+fn rate_limits() {
+    let mut config = Config::default();
+    config.rate_limits.per_ip = 4;
+    config.rate_limits.per_game = 2;
+    let client = Client::new(rocket(Some(config.clone()))).unwrap();
+    let one = "10.0.0.1:8000".parse().unwrap();
+    let two = "10.0.0.2:8000".parse().unwrap();
+    let res = client
+        .put("/api/v1/game/my_game")
+        .remote(one)
+        .body(serde_json::to_string(&PlayerData::new("p1")).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let res = client
+        .post("/api/v1/game/my_game")
+        .remote(two)
+        .body(serde_json::to_string(&PlayerData::new("p2")).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let res = client
+        .post("/api/v1/game/my_game/answer")
+        .remote(two)
+        .body(serde_json::to_string(&Answer::new("p2", "a")).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    // The second client has made its two changes to the game for this window
+    let mut res = client
+        .post("/api/v1/game/my_game/answer")
+        .remote(two)
+        .body(serde_json::to_string(&Answer::new("p2", "b")).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::TooManyRequests);
+    let retry_after = res.headers().get_one("Retry-After").unwrap().parse::<u64>().unwrap();
+    assert!((1..=60).contains(&retry_after));
+    assert!(res.body_string().unwrap().contains("RateLimited"));
+    // Which does not stop the first client changing it
+    let res = client
+        .post("/api/v1/game/my_game/answer")
+        .remote(one)
+        .body(serde_json::to_string(&Answer::new("p1", "a")).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    // Polling does not count towards the game's limit but does towards the client's
+    let res = client.get("/api/v1/game/my_game").remote(two).dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let res = client.get("/api/v1/game/my_game").remote(two).dispatch();
+    assert_eq!(res.status(), Status::TooManyRequests);
+    // Clients cannot escape their limit by claiming another address
+    let res = client
+        .get("/api/v1/game/my_game")
+        .remote(two)
+        .header(Header::new("X-Real-IP", "10.0.0.9"))
+        .dispatch();
+    assert_eq!(res.status(), Status::TooManyRequests);
+    let res = client.get("/api/v1/game/my_game").remote(one).dispatch();
+    assert_eq!(res.status(), Status::Ok);
+
+    // Behind a trusted proxy clients are told apart by the address it forwards
+    config.rate_limits.per_ip = 1;
+    config.rate_limits.trust_proxy = true;
+    let client = Client::new(rocket(Some(config))).unwrap();
+    for (forwarded, status) in [("10.0.0.1", Status::Ok), ("10.0.0.2", Status::Ok), ("10.0.0.1", Status::TooManyRequests)] {
+        let res = client
+            .get("/api/v1/lobby")
+            .remote(one)
+            .header(Header::new("X-Real-IP", forwarded))
+            .dispatch();
+        assert_eq!(res.status(), status);
+    }
+}
+
+#[test]
+fn input_lengths() {
+    let client = Client::new(rocket(None)).unwrap();
+    let long = "x".repeat(100);
+    let res = client
+        .put(format!("/api/v1/game/{}", long))
+        .body(serde_json::to_string(&PlayerData::new("p1")).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+    let mut res = client
+        .put("/api/v1/game/my_game")
+        .body(serde_json::to_string(&PlayerData::new(&long)).unwrap())
+        .dispatch();
+    assert!(res.body_string().unwrap().contains("InputTooLong"));
+    client
+        .put("/api/v1/game/my_game")
+        .body(serde_json::to_string(&PlayerData::new("p1")).unwrap())
+        .dispatch();
+    client
+        .post("/api/v1/game/my_game")
+        .body(serde_json::to_string(&PlayerData::new("p2")).unwrap())
+        .dispatch();
+    let mut res = client
+        .post("/api/v1/game/my_game/answer")
+        .body(serde_json::to_string(&Answer::new("p1", &long)).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+    assert!(res.body_string().unwrap().contains("InputTooLong"));
+    let res = client
+        .post("/api/v1/game/my_game/answer")
+        .body(serde_json::to_string(&Answer::new("p1", "a")).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+}
+
+#[test]
+fn cors_policy() {
+    // No cross-origin requests are allowed by default
+    let client = Client::new(rocket(None)).unwrap();
+    let res = client
+        .get("/api/v1/heartbeat")
+        .header(Header::new("Origin", "https://evil.example"))
+        .dispatch();
+    assert_eq!(res.status(), Status::Forbidden);
+    let res = client.get("/api/v1/heartbeat").dispatch();
+    assert_eq!(res.status(), Status::Ok);
+
+    let mut config = Config::default();
+    config.cors.allowed_origins = vec![String::from("https://weighty.example")];
+    config.cors.allowed_methods = vec![String::from("GET"), String::from("POST")];
+    let client = Client::new(rocket(Some(config))).unwrap();
+    let res = client
+        .get("/api/v1/heartbeat")
+        .header(Header::new("Origin", "https://weighty.example"))
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    assert_eq!(
+        res.headers().get_one("Access-Control-Allow-Origin"),
+        Some("https://weighty.example")
+    );
+    assert_eq!(res.headers().get_one("Access-Control-Allow-Credentials"), None);
+    let res = client
+        .get("/api/v1/heartbeat")
+        .header(Header::new("Origin", "https://evil.example"))
+        .dispatch();
+    assert_eq!(res.status(), Status::Forbidden);
+    // Preflights are checked against the allowed methods
+    let res = client
+        .options("/api/v1/game/my_game")
+        .header(Header::new("Origin", "https://weighty.example"))
+        .header(Header::new("Access-Control-Request-Method", "POST"))
+        .dispatch();
+    assert_eq!(res.status(), Status::NoContent);
+    let res = client
+        .options("/api/v1/game/my_game")
+        .header(Header::new("Origin", "https://weighty.example"))
+        .header(Header::new("Access-Control-Request-Method", "DELETE"))
+        .dispatch();
+    assert_eq!(res.status(), Status::Forbidden);
+    let res = client
+        .options("/api/v1/game/my_game")
+        .header(Header::new("Origin", "https://evil.example"))
+        .header(Header::new("Access-Control-Request-Method", "POST"))
+        .dispatch();
+    assert_eq!(res.status(), Status::Forbidden);
+}
+
+#[test]
+fn layered_config() {
+    let dir = std::env::temp_dir().join(format!("weighty-config-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("weighty.toml");
+    std::fs::write(
+        &path,
+        "[server]\nport = 9000\nlog_level = \"critical\"\n\n[game.rules]\nsteal = true\n\n[cors]\nallowed_origins = [\"https://a.example\"]\n",
+    )
+    .unwrap();
+    // The environment overrides the file and flags override both
+    std::env::set_var("WEIGHTY_SERVER_PORT", "9001");
+    std::env::set_var("WEIGHTY_GAME_RULES_MAX_HINTS", "5");
+    std::env::set_var("WEIGHTY_RATE_LIMITS_PER_IP", "10");
+    std::env::set_var("WEIGHTY_CORS_ALLOWED_ORIGINS", "https://b.example, https://c.example");
+    let config = Opt::from_iter(["weighty-inquiry", "--config", path.to_str().unwrap(), "--port", "9002"])
+        .load()
+        .unwrap();
+    assert_eq!(config.server.port, 9002);
+    assert_eq!(config.server.log_level, "critical");
+    assert!(config.game.rules.steal);
+    assert_eq!(config.game.rules.max_hints, 5);
+    assert_eq!(config.rate_limits.per_ip, 10);
+    assert_eq!(config.cors.allowed_origins, ["https://b.example", "https://c.example"]);
+    config.validate().unwrap();
+    // The dump can be loaded back
+    std::fs::write(&path, config.to_toml()).unwrap();
+    std::env::remove_var("WEIGHTY_SERVER_PORT");
+    let reloaded = Config::load(Some(&path)).unwrap();
+    assert_eq!(reloaded.server.port, 9002);
+    assert_eq!(reloaded.to_toml(), config.to_toml());
+    std::env::remove_var("WEIGHTY_GAME_RULES_MAX_HINTS");
+    std::env::remove_var("WEIGHTY_RATE_LIMITS_PER_IP");
+    std::env::remove_var("WEIGHTY_CORS_ALLOWED_ORIGINS");
+
+    // Unknown and mistyped settings are errors
+    std::env::set_var("WEIGHTY_SERVER_PROT", "9001");
+    let error = Config::load(None).unwrap_err().to_string();
+    assert!(error.contains("WEIGHTY_SERVER_PROT"), "{}", error);
+    std::env::remove_var("WEIGHTY_SERVER_PROT");
+    std::env::set_var("WEIGHTY_SERVER_PORT", "high");
+    assert!(Config::load(None).is_err());
+    std::env::remove_var("WEIGHTY_SERVER_PORT");
+    // The commit recorded at build time may still be set when the server runs
+    std::env::set_var("WEIGHTY_BUILD_COMMIT", "abc123");
+    assert!(Config::load(None).is_ok());
+    std::env::remove_var("WEIGHTY_BUILD_COMMIT");
+    // Variables which are not UTF-8 are ignored unless they are ours
+    {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+        let invalid = OsStr::from_bytes(b"\xff");
+        std::env::set_var("NOT_WEIGHTY_INVALID", invalid);
+        assert!(Config::load(None).is_ok());
+        std::env::remove_var("NOT_WEIGHTY_INVALID");
+        std::env::set_var("WEIGHTY_SERVER_HOST", invalid);
+        let error = Config::load(None).unwrap_err().to_string();
+        assert!(error.contains("WEIGHTY_SERVER_HOST: not valid UTF-8"), "{}", error);
+        std::env::remove_var("WEIGHTY_SERVER_HOST");
+    }
+    std::fs::write(&path, "[server]\nprot = 9000\n").unwrap();
+    let error = Config::load(Some(&path)).unwrap_err().to_string();
+    assert!(error.contains("prot"), "{}", error);
+
+    // Every problem is reported at once
+    let mut config = Config::default();
+    config.server.port = 0;
+    config.server.environment = String::from("moon");
+    config.questions.file = Some(dir.join("missing.txt"));
+    config.cors.allowed_methods = vec![String::from("FETCH")];
+    let error = config.validate().unwrap_err().to_string();
+    for key in ["server.port", "server.environment", "questions.file", "cors"] {
+        assert!(error.contains(key), "{}", error);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn default_rules() {
+    let mut config = Config::default();
+    config.game.rules.steal = true;
+    let client = Client::new(rocket(Some(config))).unwrap();
+    client
+        .put("/api/v1/game/my_game")
+        .body(serde_json::to_string(&PlayerData::new("p1")).unwrap())
+        .dispatch();
+    let mut res = client.get("/api/v1/game/my_game").dispatch();
+    let game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+    assert!(game.board.rules.steal);
+}
+
+#[test]
+fn metrics() {
+    let client = Client::new(rocket(None)).unwrap();
+    client
+        .put("/api/v1/game/my_game")
+        .body(serde_json::to_string(&PlayerData::new("p1")).unwrap())
+        .dispatch();
+    client
+        .post("/api/v1/game/my_game")
+        .body(serde_json::to_string(&PlayerData::new("p2")).unwrap())
+        .dispatch();
+    for player in ["p1", "p2"] {
+        client
+            .post("/api/v1/game/my_game/answer")
+            .body(serde_json::to_string(&Answer::new(player, "a")).unwrap())
+            .dispatch();
+    }
+    client.get("/api/v1/game/missing").dispatch();
+    client
+        .put("/api/v1/game/other_game")
+        .body(serde_json::to_string(&PlayerData::new("p1")).unwrap())
+        .dispatch();
+    client.delete("/api/v1/game/other_game").dispatch();
+    let mut res = client.get("/metrics").dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let body = res.body_string().unwrap();
+    for line in [
+        "weighty_games_active 1",
+        "weighty_games_created_total 2",
+        "weighty_games_deleted_total 1",
+        "weighty_rounds_completed_total 1",
+        "weighty_questions 0",
+        "weighty_answer_latency_seconds_count{round=\"1\"} 2",
+        "weighty_errors_total{error=\"GameNotFound\"} 1",
+        "weighty_http_request_duration_seconds_count{method=\"POST\",route=\"answer\"} 2",
+        "weighty_http_request_duration_seconds_bucket{method=\"PUT\",route=\"create_game\",le=\"+Inf\"} 2",
+    ] {
+        assert!(body.lines().any(|l| l == line), "{} not in\n{}", line, body);
+    }
+
+    // Later rounds share a label and finished games stop being timed
+    let metrics = Metrics::default();
+    metrics.game_created("my_game");
+    for round in 1..=12 {
+        metrics.answered("my_game", round);
+        metrics.rounds_completed("my_game", 1, round == 12);
+    }
+    assert_eq!(metrics.rounds_in_progress(), 0);
+    let body = metrics.render(0, 0);
+    for line in [
+        "weighty_answer_latency_seconds_count{round=\"4\"} 1",
+        "weighty_answer_latency_seconds_count{round=\"5-9\"} 5",
+        "weighty_answer_latency_seconds_count{round=\"10+\"} 3",
+    ] {
+        assert!(body.lines().any(|l| l == line), "{} not in\n{}", line, body);
+    }
+}
+
+/// Log output shared with the test reading it
+#[derive(Clone, Default)]
+struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn structured_logging() {
+    let buffer = LogBuffer::default();
+    // Rocket may have installed its own logger for another test but events are still written
+    let _ = logging::init(LogFormat::Json, log::LevelFilter::Info, Box::new(buffer.clone()));
+    let client = Client::new(rocket(None)).unwrap();
+    client
+        .put("/api/v1/game/logged_game")
+        .body(serde_json::to_string(&PlayerData::new("p1")).unwrap())
+        .dispatch();
+    client
+        .post("/api/v1/game/logged_game")
+        .body(serde_json::to_string(&PlayerData::new("p2")).unwrap())
+        .dispatch();
+    // A player refused a seat is not logged as joining
+    let res = client
+        .post("/api/v1/game/logged_game")
+        .body(serde_json::to_string(&PlayerData::new("p3")).unwrap())
+        .dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+    client
+        .post("/api/v1/game/logged_game/answer")
+        .body(serde_json::to_string(&Answer::new("p1", "a")).unwrap())
+        .dispatch();
+    let res = client
+        .post("/api/v1/game/logged_game/answer")
+        .header(Header::new("X-Request-Id", "trace-me"))
+        .body(serde_json::to_string(&Answer::new("p2", "zebra")).unwrap())
+        .dispatch();
+    assert_eq!(res.headers().get_one("X-Request-Id"), Some("trace-me"));
+    let output = String::from_utf8(buffer.0.lock().clone()).unwrap();
+    let lines = output
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .filter(|line| line["game_id"] == "logged_game")
+        .collect::<Vec<_>>();
+    let events = lines
+        .iter()
+        .filter(|line| line["target"] == "weighty::event")
+        .map(|line| line["message"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        events,
+        [
+            "player_joined",
+            "game_created",
+            "request_finished",
+            "player_joined",
+            "request_finished",
+            "request_finished",
+            "answered",
+            "request_finished",
+            "answered",
+            "round_completed",
+            "request_finished",
+        ]
+    );
+    // Every line has a request id and the final request's lines share the client's id
+    assert!(lines.iter().all(|line| line["request_id"].is_string()));
+    let traced = lines.iter().filter(|line| line["request_id"] == "trace-me").collect::<Vec<_>>();
+    let answered = traced.iter().find(|line| line["message"] == "answered").unwrap();
+    assert_eq!(answered["player"], "p2");
+    // Only the length of an answer is logged, never its text
+    assert_eq!(answered["length"], "5");
+    assert!(answered.get("answer").is_none());
+    assert!(traced.iter().any(|line| line["message"] == "request_finished" && line["status"] == "200"));
+}
+
+#[test]
+fn admin_commands() {
+    // Subcommands take the storage and question flags given before them
+    let cli = Cli::from_iter(["weighty-inquiry", "--snapshot", "games.json", "storage", "compact", "--older-than", "7"]);
+    assert!(matches!(
+        cli.command,
+        Some(Command::Storage(StorageCommand::Compact { older_than: 7, unfinished: false }))
+    ));
+    assert_eq!(cli.opt.load().unwrap().storage.snapshot, Some("games.json".into()));
+
+    let path = std::env::temp_dir().join(format!("weighty-pack-{}.txt", std::process::id()));
+    std::fs::write(&path, "Fruits\nColours\n\nfruits\n Animals\n").unwrap();
+    let mut questions = QuestionLookup::default();
+    questions.populate_from_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        questions.problems(),
+        vec![
+            "line 3: blank question",
+            "line 4: duplicate of line 1",
+            "line 5: leading or trailing whitespace",
+        ]
+    );
+    assert_eq!(QuestionLookup::default().problems(), vec!["the pack has no questions"]);
+
+    // Simulated matches are played to the end
+    let mut words = WordLookup::default();
+    words
+        .populate_from_file(&std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("words.txt"))
+        .unwrap();
+    let bots = [Bot::new(Difficulty::Greedy), Bot::new(Difficulty::Random)];
+    let bots = bots.map(|mut bot| {
+        bot.name.push_str(&format!(" {:?}", bot.difficulty));
+        bot
+    });
+    let game = simulate(Rules::default(), bots.clone(), &QuestionLookup::default(), &words, 500).unwrap();
+    assert!(game.finished);
+    assert_eq!(game.player_two.as_deref(), Some("Bot Random"));
+    let description = describe("simulation", &game);
+    assert!(description.starts_with("game simulation"), "{}", description);
+    assert!(description.contains("captured"), "{}", description);
+    // Or stopped at the round limit
+    let game = simulate(Rules::default(), bots, &QuestionLookup::default(), &words, 2).unwrap();
+    assert!(game.finished || game.completed_rounds() == 2);
+
+    // Compacting keeps recent and unfinished games
+    let mut old = game.clone();
+    old.finished = true;
+    old.created_at = 100;
+    let mut unfinished = game.clone();
+    unfinished.finished = false;
+    unfinished.created_at = 100;
+    let mut snapshot = Snapshot::new(
+        vec![
+            GameExport::new("old", &old),
+            GameExport::new("unfinished", &unfinished),
+            GameExport::new("recent", &game),
+        ],
+        Vec::new(),
+    );
+    assert_eq!(snapshot.compact(1000, false), 1);
+    assert!(snapshot.game("old").is_none());
+    assert!(snapshot.game("unfinished").is_some());
+    assert_eq!(snapshot.compact(1000, true), 1);
+    assert_eq!(snapshot.games.len(), 1);
+    assert!(snapshot.game("recent").is_some());
+
+    // However far back games are kept the cutoff does not overflow
+    let path = std::env::temp_dir().join(format!("weighty-compact-{}.json", std::process::id()));
+    snapshot.write(&path).unwrap();
+    let mut config = Config::default();
+    config.storage.snapshot = Some(path.clone());
+    let command = Command::Storage(StorageCommand::Compact {
+        older_than: u64::MAX,
+        unfinished: true,
+    });
+    command.run(&config).unwrap();
+    assert_eq!(Snapshot::read(&path).unwrap().unwrap().games.len(), 1);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn admin_api() {
+    const TOKEN: &str = "an-admin-token-for-tests";
+    let auth = || Header::new("Authorization", format!("Bearer {}", TOKEN));
+    // Without a configured token every admin request is refused
+    let client = Client::new(rocket(None)).unwrap();
+    let mut res = client.get("/api/v1/admin/games").header(auth()).dispatch();
+    assert_eq!(res.status(), Status::Unauthorized);
+    assert!(res.body_string().unwrap().contains("Unauthorized"));
+
+    let mut config = Config::default();
+    config.admin.token = Some(TOKEN.to_string());
+    assert!(config.to_toml().contains("<redacted>"));
+    let client = Client::new(rocket(Some(config))).unwrap();
+    let res = client
+        .get("/api/v1/admin/games")
+        .header(Header::new("Authorization", "Bearer wrong"))
+        .dispatch();
+    assert_eq!(res.status(), Status::Unauthorized);
+    for (player, method) in [("p1", "put"), ("p2", "post")] {
+        let body = serde_json::to_string(&PlayerData::new(player)).unwrap();
+        let req = if method == "put" {
+            client.put("/api/v1/game/my_game")
+        } else {
+            client.post("/api/v1/game/my_game")
+        };
+        assert_eq!(req.body(body).dispatch().status(), Status::Ok);
+    }
+    client
+        .put("/api/v1/game/waiting")
+        .body(serde_json::to_string(&PlayerData::new("p3")).unwrap())
+        .dispatch();
+    client
+        .post("/api/v1/game/my_game/answer")
+        .body(serde_json::to_string(&Answer::new("p1", "rude")).unwrap())
+        .dispatch();
+
+    // List every game
+    let mut res = client.get("/api/v1/admin/games").header(auth()).dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let summaries = serde_json::from_str::<Vec<GameSummary>>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(summaries.len(), 2);
+    let summary = summaries.iter().find(|summary| summary.game_id == "my_game").unwrap();
+    assert_eq!(summary.player_two.as_deref(), Some("p2"));
+    assert_eq!(summary.rounds, 1);
+    assert_eq!(summary.player_one_captured, 0);
+    assert!(summary.idle < 5);
+
+    // Clear an answer to the current round so the player answers again
+    let edit = r#"{"player":"p1","round":1}"#;
+    let res = client.put("/api/v1/admin/game/my_game/answer").header(auth()).body(edit).dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let mut res = client.get("/api/v1/game/my_game").dispatch();
+    let game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(game.current_round().player_one_answer, None);
+    let edit = r#"{"player":"p1","round":2,"answer":"nice"}"#;
+    let res = client.put("/api/v1/admin/game/my_game/answer").header(auth()).body(edit).dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+
+    // Force a stuck round through
+    client
+        .post("/api/v1/game/my_game/answer")
+        .body(serde_json::to_string(&Answer::new("p2", "zebra")).unwrap())
+        .dispatch();
+    let res = client.post("/api/v1/admin/game/my_game/advance").header(auth()).dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let res = client.post("/api/v1/admin/game/waiting/advance").header(auth()).dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+    let mut res = client.get("/api/v1/game/my_game").dispatch();
+    let game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(game.rounds.len(), 2);
+    assert_eq!(game.rounds[0].player_one_answer.as_deref(), Some(""));
+    assert_eq!(game.rounds[0].player_two_answer.as_deref(), Some("zebra"));
+
+    // Clearing an answer to a completed round leaves a placeholder
+    let edit = r#"{"player":"p2","round":1}"#;
+    let res = client.put("/api/v1/admin/game/my_game/answer").header(auth()).body(edit).dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    // Rename a player, to a placeholder if no name is given
+    let edit = r#"{"player":"p2","name":"p1"}"#;
+    let res = client.put("/api/v1/admin/game/my_game/player").header(auth()).body(edit).dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+    let edit = r#"{"player":"p2"}"#;
+    let res = client.put("/api/v1/admin/game/my_game/player").header(auth()).body(edit).dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let mut res = client.get("/api/v1/game/my_game").dispatch();
+    let game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(game.rounds[0].player_two_answer.as_deref(), Some("[removed]"));
+    assert_eq!(game.player_two.as_deref(), Some("Player Two"));
+
+    // Broadcast a maintenance notice
+    let res = client.get("/api/v1/notice").dispatch();
+    assert_eq!(res.status(), Status::NotFound);
+    let res = client
+        .put("/api/v1/admin/notice")
+        .header(auth())
+        .body(r#"{"message":"Restarting in 5 minutes"}"#)
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let mut res = client.get("/api/v1/notice").dispatch();
+    let notice = serde_json::from_str::<Notice>(&res.body_string().unwrap()).unwrap();
+    assert_eq!(notice.message, "Restarting in 5 minutes");
+    let res = client.get("/api/v1/game/my_game").dispatch();
+    assert_eq!(res.headers().get_one("X-Notice"), Some(notice.posted_at.to_string().as_str()));
+    let res = client.delete("/api/v1/admin/notice").header(auth()).dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let res = client.get("/api/v1/game/my_game").dispatch();
+    assert_eq!(res.headers().get_one("X-Notice"), None);
+}
+
+#[test]
+fn content_filter() {
+    let mut filter = ContentFilter::default();
+    filter.insert("darn");
+    filter.insert("heck*");
+    filter.insert("*");
+    assert_eq!(filter.mask("a fine answer"), None);
+    assert_eq!(filter.mask("D4RN it").as_deref(), Some("**** it"));
+    assert_eq!(filter.mask("daaarrrn  heckin!").as_deref(), Some("********  *******"));
+    assert_eq!(filter.mask("d@rn_"), Some(String::from("*****")));
+    // Only whole words are blocked, apart from prefix entries
+    assert_eq!(filter.mask("darned"), None);
+    assert_eq!(filter.mask("checked"), None);
+
+    const TOKEN: &str = "an-admin-token-for-tests";
+    let auth = || Header::new("Authorization", format!("Bearer {}", TOKEN));
+    let path = std::env::temp_dir().join(format!("weighty-blocked-{}.txt", std::process::id()));
+    std::fs::write(&path, "# Mild words for testing\n\ndarn\nheck*\n").unwrap();
+    let mut config = Config::default();
+    config.filter.words_file = Some(path.clone());
+    config.admin.token = Some(TOKEN.to_string());
+    let client = Client::new(rocket(Some(config))).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    // Games are only filtered in family mode
+    let res = client.put("/api/v1/game/open").body(r#"{"player":"darn"}"#).dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let res = client
+        .put("/api/v1/game/family")
+        .body(r#"{"player":"Darn","family_mode":true}"#)
+        .dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+    let res = client
+        .put("/api/v1/game/family")
+        .body(r#"{"player":"p1","family_mode":true}"#)
+        .dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let res = client.post("/api/v1/game/family").body(r#"{"player":"h3ck"}"#).dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+    let res = client.post("/api/v1/game/family/bot").body(r#"{"name":"Heckbot"}"#).dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+    let res = client.post("/api/v1/game/family").body(r#"{"player":"p2"}"#).dispatch();
+    assert_eq!(res.status(), Status::Ok);
+
+    // Answers are masked rather than rejected
+    let answer = serde_json::to_string(&Answer::new("p1", "oh darn")).unwrap();
+    let res = client.post("/api/v1/game/family/answer").body(answer).dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let mut res = client.get("/api/v1/game/family").dispatch();
+    let game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+    assert!(game.family_mode);
+    assert_eq!(game.current_round().player_one_answer.as_deref(), Some("oh ****"));
+
+    // In flag mode text is let through and recorded for review
+    let mut games = Games::default();
+    games.filter.insert("darn");
+    games.filter.mode = FilterMode::Flag;
+    let player = String::from("darn");
+    let name = games.screen(None, true, &player, Field::Name, player.clone()).unwrap();
+    assert_eq!(name, "darn");
+    games.screen(Some("my_game"), true, &player, Field::Name, player.clone()).unwrap();
+    let flags = games.flags.lock().clone();
+    assert_eq!(flags.len(), 1);
+    assert_eq!(flags[0].game_id.as_deref(), Some("my_game"));
+    assert_eq!(flags[0].field, Field::Name);
+
+    let res = client.get("/api/v1/admin/flags").header(auth()).dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let res = client.delete("/api/v1/admin/flags").dispatch();
+    assert_eq!(res.status(), Status::Unauthorized);
+    let mut res = client.get("/api/v1/admin/flags").header(auth()).dispatch();
+    assert!(serde_json::from_str::<Vec<Flag>>(&res.body_string().unwrap()).unwrap().is_empty());
+
+    // Flags are kept across restarts and only the most recent are kept
+    for i in 1..MAX_FLAGS {
+        let player = format!("darn{}", i);
+        games.screen(None, true, &player, Field::Name, String::from("darn")).unwrap();
+    }
+    let flags = games.flags.lock().clone();
+    assert_eq!(flags.len(), MAX_FLAGS);
+    assert_eq!(flags[0].game_id.as_deref(), Some("my_game"));
+    assert_eq!(flags[1].player, "darn1");
+    games.screen(None, true, &player, Field::Answer, String::from("oh darn")).unwrap();
+    assert_eq!(games.flags.lock().front().unwrap().player, "darn1");
+    let json = serde_json::to_string(&Snapshot::take(&games)).unwrap();
+    let restored = Games::default();
+    serde_json::from_str::<Snapshot>(&json).unwrap().restore(&restored).unwrap();
+    assert_eq!(*restored.flags.lock(), *games.flags.lock());
+    // Games outside family mode are not checked at all
+    assert_eq!(games.screen(None, false, &player, Field::Answer, String::from("darn")).unwrap(), "darn");
+    assert_eq!(games.flags.lock().len(), MAX_FLAGS);
+
+    // Profile names are screened by the server's default
+    let path = std::env::temp_dir().join(format!("weighty-blocked-profile-{}.txt", std::process::id()));
+    std::fs::write(&path, "darn\n").unwrap();
+    let mut config = Config::default();
+    config.filter.words_file = Some(path.clone());
+    config.filter.family_mode = true;
+    let client = Client::new(rocket(Some(config))).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let res = client.post("/api/v1/profile").body(r#"{"name":"DARN"}"#).dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+    let res = client.post("/api/v1/profile").body(r#"{"name":"p1"}"#).dispatch();
+    assert_eq!(res.status(), Status::Ok);
+}
+
+#[test]
+fn input_validation() {
+    let limits = InputLimits::default();
+    assert!(limits.player_name("Zoë 🎲").is_ok());
+    assert!(matches!(limits.player_name("  "), Err(Error::InputEmpty)));
+    assert!(matches!(limits.player_name("p1\u{7}"), Err(Error::InvalidCharacters)));
+    assert!(matches!(limits.player_name("p\u{202e}1"), Err(Error::InvalidCharacters)));
+    assert!(limits.answer("").is_ok());
+    assert!(matches!(limits.answer("a\nb"), Err(Error::InvalidCharacters)));
+    assert!(limits.game_id("my-game_2").is_ok());
+    assert!(matches!(limits.game_id("my game"), Err(Error::InvalidCharacters)));
+
+    let config = Config {
+        input_limits: InputLimits {
+            player_name: 4,
+            answer: 8,
+            game_id: 6,
+        },
+        ..Config::default()
+    };
+    let client = Client::new(rocket(Some(config))).unwrap();
+    let mut res = client.put("/api/v1/game/abcdefg").body(r#"{"player":"p1"}"#).dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+    assert!(res.body_string().unwrap().contains("InputTooLong"));
+    let mut res = client.put("/api/v1/game/g%20g").body(r#"{"player":"p1"}"#).dispatch();
+    assert!(res.body_string().unwrap().contains("InvalidCharacters"));
+    let mut res = client.put("/api/v1/game/abc").body(r#"{"player":"p\u0000"}"#).dispatch();
+    assert!(res.body_string().unwrap().contains("InvalidCharacters"));
+    let mut res = client.put("/api/v1/game/abc").body(r#"{"player":"player"}"#).dispatch();
+    assert!(res.body_string().unwrap().contains("InputTooLong"));
+    let mut res = client.post("/api/v1/game").body(r#"{"game_id":"a/b","player":"p1"}"#).dispatch();
+    assert!(res.body_string().unwrap().contains("InvalidCharacters"));
+    let mut res = client.post("/api/v1/profile").body(r#"{"name":""}"#).dispatch();
+    assert!(res.body_string().unwrap().contains("InputEmpty"));
+    let mut res = client.post("/api/v1/matchmaking").body(r#"{"player":"\t"}"#).dispatch();
+    assert!(res.body_string().unwrap().contains("InputEmpty"));
+
+    let res = client.put("/api/v1/game/abc").body(r#"{"player":"p1"}"#).dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let res = client.post("/api/v1/game/abc").body(r#"{"player":"p2"}"#).dispatch();
+    assert_eq!(res.status(), Status::Ok);
+    let mut res = client
+        .post("/api/v1/game/abc/answer")
+        .body(r#"{"player":"p1","answer":"too long!"}"#)
+        .dispatch();
+    assert!(res.body_string().unwrap().contains("InputTooLong"));
+    // Bodies which are not the expected JSON are refused before validation
+    let res = client.post("/api/v1/game/abc/answer").body(r#"{"player":"p1"}"#).dispatch();
+    assert_eq!(res.status(), Status::UnprocessableEntity);
+    let res = client.post("/api/v1/game/abc/answer").body("{").dispatch();
+    assert_eq!(res.status(), Status::BadRequest);
+
+    // Games imported without a request, such as from a snapshot, are checked too
+    let games = Games::default();
+    let data = CreateGameData::new("p1", Rules::default());
+    games.create(String::from("g"), data, String::new(), String::new()).unwrap();
+    let mut export = games.export("g").unwrap();
+    export.game.player_one = Some(String::from("p\u{7}"));
+    let snapshot = Snapshot::new(vec![export], Vec::new());
+    let restored = Games::default();
+    assert!(matches!(snapshot.restore(&restored), Err(SnapshotError::Game(_, Error::InvalidCharacters))));
+
+    let mut config = Config::default();
+    config.input_limits.answer = 0;
+    assert!(config.validate().unwrap_err().to_string().contains("input_limits.answer"));
+}
+
+/// Text mixing ordinary, escaped, multibyte, invisible and control characters
+fn fuzz_text(rng: &mut StdRng) -> String {
+    const CHARS: &[char] = &[
+        'a', 'Z', '7', ' ', '-', '_', '/', '"', '\\', '%', 'é', '🎲', '\u{0}', '\n', '\u{7f}', '\u{200b}',
+        '\u{202e}', '\u{feff}',
+    ];
+    let length = if rng.gen_bool(0.1) { rng.gen_range(0..2000) } else { rng.gen_range(0..12) };
+    (0..length).map(|_| *CHARS.choose(rng).unwrap()).collect()
+}
+
+/// Replace a random part of `value` with a random string or a value of another type
+fn fuzz_value(value: &mut serde_json::Value, rng: &mut StdRng) {
+    use serde_json::Value;
+    match value {
+        Value::Object(object) if !object.is_empty() && rng.gen_bool(0.8) => {
+            let key = object.keys().cloned().collect::<Vec<_>>().choose(rng).unwrap().clone();
+            fuzz_value(object.get_mut(&key).unwrap(), rng);
+        }
+        _ => {
+            *value = match rng.gen_range(0..6) {
+                0 => Value::Null,
+                1 => Value::from(rng.gen::<i64>()),
+                2 => Value::from(rng.gen::<bool>()),
+                3 => Value::Array(vec![Value::from(fuzz_text(rng))]),
+                _ => Value::from(fuzz_text(rng)),
+            }
+        }
+    }
+}
+
+/// Flip, insert or drop random bytes of `body`
+fn fuzz_bytes(body: &str, rng: &mut StdRng) -> Vec<u8> {
+    let mut bytes = body.as_bytes().to_vec();
+    for _ in 0..rng.gen_range(1..4) {
+        let index = rng.gen_range(0..=bytes.len());
+        match rng.gen_range(0..3) {
+            0 if index < bytes.len() => bytes[index] = rng.gen(),
+            1 => bytes.insert(index, rng.gen()),
+            _ => bytes.truncate(index),
+        }
+    }
+    bytes
+}
+
+#[test]
+fn fuzz_json_entry_points() {
+    const TOKEN: &str = "an-admin-token-for-tests";
+    let mut config = Config::default();
+    config.admin.token = Some(TOKEN.to_string());
+    config.rate_limits.per_ip = 0;
+    config.rate_limits.per_game = 0;
+    let client = Client::new(rocket(Some(config))).unwrap();
+    let mut rng = StdRng::seed_from_u64(50);
+    for (player, method) in [("p1", Method::Put), ("p2", Method::Post)] {
+        let body = serde_json::to_string(&PlayerData::new(player)).unwrap();
+        let res = client.req(method, "/api/v1/game/fuzz").body(body).dispatch();
+        assert_eq!(res.status(), Status::Ok);
+    }
+    for answer in [Answer::new("p1", "apple"), Answer::new("p2", "zebra")] {
+        client
+            .post("/api/v1/game/fuzz/answer")
+            .body(serde_json::to_string(&answer).unwrap())
+            .dispatch();
+    }
+    // Imports are seeded with a real export so mutations reach the checks behind deserializing
+    let mut res = client.get("/api/v1/game/fuzz/export").dispatch();
+    let mut export = serde_json::from_str::<GameExport>(&res.body_string().unwrap()).unwrap();
+    export.game_id = String::from("fuzz_import");
+    let import_seed = serde_json::to_string(&export).unwrap();
+    let entry_points = [
+        (Method::Put, "/api/v1/game/fuzz_create", r#"{"player":"p1","public":true,"best_of":3}"#),
+        (Method::Post, "/api/v1/game", r#"{"game_id":"fuzz_new","player":"p1"}"#),
+        (Method::Post, "/api/v1/matchmaking", r#"{"player":"p1","fallback":{"kind":"cancel"}}"#),
+        (Method::Post, "/api/v1/profile", r#"{"name":"p1"}"#),
+        (Method::Post, "/api/v1/game/fuzz", r#"{"player":"p3"}"#),
+        (Method::Post, "/api/v1/game/fuzz/bot", r#"{"name":"Bot","difficulty":"normal"}"#),
+        (Method::Post, "/api/v1/game/fuzz/hint", r#"{"player":"p1"}"#),
+        (Method::Post, "/api/v1/game/fuzz/answer", r#"{"player":"p1","answer":"apple"}"#),
+        (Method::Post, "/api/v1/import", import_seed.as_str()),
+        (Method::Put, "/api/v1/admin/game/fuzz/answer", r#"{"player":"p1","round":1,"answer":"pear"}"#),
+        (Method::Put, "/api/v1/admin/game/fuzz/player", r#"{"player":"p2","name":"p4"}"#),
+    ];
+
+    let expected = [
+        Status::Ok,
+        Status::BadRequest,
+        Status::NotFound,
+        Status::Conflict,
+        Status::UnprocessableEntity,
+    ];
+    let mut imports = HashMap::new();
+    for _ in 0..100 {
+        for (method, path, seed) in &entry_points {
+            let body = if rng.gen_bool(0.5) {
+                let mut value = serde_json::from_str::<serde_json::Value>(seed).unwrap();
+                fuzz_value(&mut value, &mut rng);
+                value.to_string().into_bytes()
+            } else {
+                fuzz_bytes(seed, &mut rng)
+            };
+            let res = client
+                .req(*method, *path)
+                .header(Header::new("Authorization", format!("Bearer {}", TOKEN)))
+                .body(&body)
+                .dispatch();
+            assert!(
+                expected.contains(&res.status()),
+                "{} {} with {:?} returned {}",
+                method,
+                path,
+                String::from_utf8_lossy(&body),
+                res.status()
+            );
+            if *path == "/api/v1/import" {
+                *imports.entry(res.status()).or_insert(0) += 1;
+                client.delete("/api/v1/game/fuzz_import").dispatch();
+            }
+        }
+    }
+    // Some mutated exports were restored and some were refused after deserializing
+    assert!(imports.get(&Status::Ok).is_some());
+    assert!(imports.get(&Status::BadRequest).is_some());
+
+    // Nothing which broke the limits was stored
+    let limits = InputLimits::default();
+    let mut res = client
+        .get("/api/v1/admin/games")
+        .header(Header::new("Authorization", format!("Bearer {}", TOKEN)))
+        .dispatch();
+    let summaries = serde_json::from_str::<Vec<GameSummary>>(&res.body_string().unwrap()).unwrap();
+    assert!(!summaries.is_empty());
+    for summary in summaries {
+        limits.game_id(&summary.game_id).unwrap();
+        let mut res = client.get(format!("/api/v1/game/{}", summary.game_id)).dispatch();
+        let game = serde_json::from_str::<Game>(&res.body_string().unwrap()).unwrap();
+        for player in game.player_one.iter().chain(&game.player_two) {
+            limits.player_name(player).unwrap();
+        }
+        for round in &game.rounds {
+            for answer in round.player_one_answer.iter().chain(&round.player_two_answer) {
+                limits.answer(answer).unwrap();
+            }
+        }
+    }
+}
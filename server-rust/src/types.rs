@@ -1,23 +1,57 @@
 mod traits;
 
-use parking_lot::Mutex;
-use rocket::State;
+use parking_lot::{Condvar, Mutex, RwLock};
 use serde::{Deserialize, Serialize};
 use core::str;
 #[cfg(test)]
 use std::iter::FromIterator;
 use std::{
-    collections::{HashMap, HashSet, BTreeMap, BTreeSet},
+    collections::{hash_map::Entry, HashMap, HashSet, BTreeMap, BTreeSet, VecDeque},
     error, fmt, hash::Hash,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use crate::question_lookup::QuestionLookup;
+use crate::{
+    admin::Notice,
+    bot::Bot,
+    filter::{ContentFilter, Field, FilterMode, Flag},
+    export::GameExport,
+    game_code, logging,
+    metrics::Metrics,
+    profiles::{GameResult, ProfileId, Profiles},
+    question_lookup::QuestionLookup,
+    validation::InputLimits,
+    word_lookup::WordLookup,
+};
 
 
 pub(crate) type Result<T> = std::result::Result<T, Error>;
 pub(crate) type Player = String;
 pub(crate) type Prompt = String;
 
+/// What a moderator's cleared answer to a completed round is replaced with
+const REMOVED_ANSWER: &str = "[removed]";
+
+/// Reject `value` if it is longer than `max` characters
+pub(crate) fn check_length(value: &str, max: usize) -> Result<()> {
+    if value.chars().count() > max {
+        Err(Error::InputTooLong)
+    } else {
+        Ok(())
+    }
+}
+
+/// The current time in seconds since the Unix epoch
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
 #[derive(Serialize, Debug)]
 pub(crate) enum Error {
     GameConflict,
@@ -26,6 +60,24 @@ pub(crate) enum Error {
     PlayerNotFound,
     RoundNotInStartState,
     RoundNotInCollectingAnswersState,
+    InvalidRules,
+    HintLimitReached,
+    GameOver,
+    GameNotOver,
+    ProfileNotFound,
+    ProfileConflict,
+    UnsupportedSchemaVersion,
+    InvalidExport,
+    VersionConflict,
+    InputTooLong,
+    InputEmpty,
+    InvalidCharacters,
+    RateLimited,
+    ShuttingDown,
+    Unauthorized,
+    RoundNotFound,
+    AnswerNotFound,
+    InappropriateContent,
 }
 
 impl fmt::Display for Error {
@@ -39,6 +91,24 @@ impl fmt::Display for Error {
             Self::RoundNotInCollectingAnswersState => {
                 write!(f, "round not in collecting answer state")
             }
+            Self::InvalidRules => write!(f, "invalid rules"),
+            Self::HintLimitReached => write!(f, "hint limit reached"),
+            Self::GameOver => write!(f, "game over"),
+            Self::GameNotOver => write!(f, "game not over"),
+            Self::ProfileNotFound => write!(f, "profile not found"),
+            Self::ProfileConflict => write!(f, "profile conflict"),
+            Self::UnsupportedSchemaVersion => write!(f, "unsupported schema version"),
+            Self::InvalidExport => write!(f, "invalid export"),
+            Self::VersionConflict => write!(f, "version conflict"),
+            Self::InputTooLong => write!(f, "input too long"),
+            Self::InputEmpty => write!(f, "input empty"),
+            Self::InvalidCharacters => write!(f, "invalid characters"),
+            Self::RateLimited => write!(f, "rate limited"),
+            Self::ShuttingDown => write!(f, "shutting down"),
+            Self::Unauthorized => write!(f, "unauthorized"),
+            Self::RoundNotFound => write!(f, "round not found"),
+            Self::AnswerNotFound => write!(f, "answer not found"),
+            Self::InappropriateContent => write!(f, "inappropriate content"),
         }
     }
 }
@@ -60,12 +130,48 @@ impl BadRequest {
     }
 }
 
+/// The `If-Match` header of a request, used to reject changes made from a stale game
+pub(crate) struct IfMatch(pub(crate) Option<String>);
+
+impl IfMatch {
+    pub(crate) fn check(&self, game: &Game) -> Result<()> {
+        match &self.0 {
+            Some(etag) if etag != "*" && etag != &game.etag() => Err(Error::VersionConflict),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The `If-None-Match` header of a request
+pub(crate) struct IfNoneMatch(pub(crate) Option<String>);
+
+/// A game in response to a poll, omitted if the client already has the current version
+pub(crate) struct Polled {
+    pub(crate) etag: String,
+    pub(crate) game: Option<Game>,
+}
+
+impl Polled {
+    pub(crate) fn new(game: &Game, if_none_match: &IfNoneMatch) -> Self {
+        let etag = game.etag();
+        let modified = match &if_none_match.0 {
+            Some(tag) => tag != "*" && tag != &etag,
+            None => true,
+        };
+        Self {
+            etag,
+            game: if modified { Some(game.clone()) } else { None },
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 pub(crate) struct PromptData {
     pub(crate) prompt: Prompt,
 }
 
 #[cfg(test)]
+#[allow(dead_code)]
 impl PromptData {
     pub(crate) fn new(prompt: &str) -> Self {
         Self {
@@ -78,6 +184,9 @@ impl PromptData {
 pub(crate) struct PlayerData {
     /// The player with which the request is associated
     pub(crate) player: Player,
+    /// The profile the player's results are recorded to
+    #[serde(default)]
+    pub(crate) profile: Option<ProfileId>,
 }
 
 #[cfg(test)]
@@ -85,19 +194,107 @@ impl PlayerData {
     pub(crate) fn new(player: &str) -> Self {
         Self {
             player: Player::from(player),
+            profile: None,
         }
     }
 }
 
+#[derive(Deserialize, Serialize)]
+pub(crate) struct CreateGameData {
+    /// The player creating the game
+    pub(crate) player: Player,
+    /// The profile the player's results are recorded to
+    #[serde(default)]
+    pub(crate) profile: Option<ProfileId>,
+    /// The rule variants to play with, the server's default rules if not given
+    #[serde(default)]
+    pub(crate) rules: Option<Rules>,
+    /// Whether the game is listed in the lobby
+    #[serde(default)]
+    pub(crate) public: bool,
+    /// The number of games in the series this game starts
+    #[serde(default = "default_best_of")]
+    pub(crate) best_of: usize,
+    /// Whether names and answers are run through the content filter, the server's default if not
+    /// given
+    #[serde(default)]
+    pub(crate) family_mode: Option<bool>,
+}
+
+fn default_best_of() -> usize {
+    1
+}
+
+#[cfg(test)]
+impl CreateGameData {
+    pub(crate) fn new(player: &str, rules: Rules) -> Self {
+        Self {
+            player: Player::from(player),
+            profile: None,
+            rules: Some(rules),
+            public: false,
+            best_of: default_best_of(),
+            family_mode: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct LobbyGame {
+    pub(crate) game_id: String,
+    /// The players already seated
+    pub(crate) players: Vec<Player>,
+    pub(crate) open_seats: usize,
+    /// A description of each rule which differs from the defaults
+    pub(crate) rules: Vec<String>,
+    /// How long ago the game was created in seconds
+    pub(crate) age: u64,
+}
+
+/// A game as seen by moderators
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct GameSummary {
+    pub(crate) game_id: String,
+    pub(crate) player_one: Option<Player>,
+    pub(crate) player_two: Option<Player>,
+    /// Whether one of the players is a bot
+    pub(crate) bot: bool,
+    /// The number of rounds started, including the current one
+    pub(crate) rounds: usize,
+    /// The number of letters each side has captured
+    pub(crate) player_one_captured: usize,
+    pub(crate) player_two_captured: usize,
+    pub(crate) finished: bool,
+    pub(crate) version: u64,
+    /// How long since the game last changed in seconds
+    pub(crate) idle: u64,
+}
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct NewGameData {
+    /// A custom id to use instead of a generated code
+    #[serde(default)]
+    pub(crate) game_id: Option<String>,
+    #[serde(flatten)]
+    pub(crate) game: CreateGameData,
+}
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct CreatedGame {
+    /// The id of the new game
+    pub(crate) game_id: String,
+    /// The client page other players open to join the game
+    pub(crate) join_url: String,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Hash)]
 pub(crate) struct Answer {
     /// The player who gave the answer
-    player: Player,
+    pub(crate) player: Player,
     /// The answer to the question for the round
     pub answer: String,
 }
 
-#[cfg(test)]
 impl Answer {
     pub(crate) fn new(player: &str, answer: &str) -> Self {
         Self {
@@ -116,6 +313,7 @@ pub(crate) struct Guess {
 }
 
 #[cfg(test)]
+#[allow(dead_code)]
 impl Guess {
     pub(crate) fn new(player: &str, guess: Vec<Answer>) -> Self {
         Self {
@@ -125,6 +323,16 @@ impl Guess {
     }
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub(crate) struct Hint {
+    /// A candidate answer for the player's question
+    pub(crate) answer: String,
+    /// How much the answer would improve the player's position on the board
+    pub(crate) gain: i32,
+    /// The letters the answer would capture
+    pub(crate) captures: Vec<char>,
+}
+
 #[derive(PartialEq)]
 pub(crate) enum RoundState {
     Start,
@@ -140,6 +348,9 @@ pub(crate) struct Round {
     /// The list of answers given, one per player
     pub player_one_answer: Option<String>,
     pub player_two_answer: Option<String>,
+    /// The changes the round made to the board once both answers were given
+    #[serde(default)]
+    pub(crate) deltas: Vec<BoardDelta>,
 }
 
 impl Round {
@@ -149,6 +360,21 @@ impl Round {
             player_two_question,
             player_one_answer: None,
             player_two_answer: None,
+            deltas: Vec::new(),
+        }
+    }
+
+    pub(crate) fn question(&self, side: Side) -> &str {
+        match side {
+            Side::PlayerOne => &self.player_one_question,
+            Side::PlayerTwo => &self.player_two_question,
+        }
+    }
+
+    pub(crate) fn answer(&self, side: Side) -> Option<&String> {
+        match side {
+            Side::PlayerOne => self.player_one_answer.as_ref(),
+            Side::PlayerTwo => self.player_two_answer.as_ref(),
         }
     }
 
@@ -167,64 +393,593 @@ impl Round {
     // }
 }
 
+/// One of the two seats in a game
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub(crate) enum Side {
+    PlayerOne,
+    PlayerTwo,
+}
+
+impl Side {
+    /// The direction this side pushes letters on the board
+    fn direction(self) -> i32 {
+        match self {
+            Self::PlayerOne => 1,
+            Self::PlayerTwo => -1,
+        }
+    }
+
+    fn opponent(self) -> Self {
+        match self {
+            Self::PlayerOne => Self::PlayerTwo,
+            Self::PlayerTwo => Self::PlayerOne,
+        }
+    }
+}
+
+/// A change to the board caused by a completed round
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum BoardDelta {
+    /// A letter moved from one value to another
+    Moved { letter: char, from: i32, to: i32 },
+    /// An uncaptured letter was captured by a side
+    Captured { letter: char, side: Side },
+    /// A captured letter was taken from the other side
+    Stolen { letter: char, side: Side },
+    /// A side gave up one of its captured letters to stop the capture of another
+    Sacrificed { letter: char, side: Side, saved: char },
+}
+
+/// Per-side settings used to even out matches between players of different skill
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub(crate) struct Handicap {
+    /// How far past zero this side must push a letter to capture it
+    pub(crate) capture_threshold: i32,
+    /// How far each letter starts pushed toward this side
+    pub(crate) offsets: BTreeMap<char, i32>,
+    /// Letters this side has captured before the first round
+    pub(crate) captured: BTreeSet<char>,
+}
+
+impl Default for Handicap {
+    fn default() -> Self {
+        Self {
+            capture_threshold: DEFAULT_CAPTURE_THRESHOLD,
+            offsets: BTreeMap::new(),
+            captured: BTreeSet::new(),
+        }
+    }
+}
+
+/// Optional rule variants applied when moving the board
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub(crate) struct Rules {
+    /// Each vowel in an answer pushes the letter the opponent leads on most back by one
+    pub(crate) neutral_vowels: bool,
+    /// Letters which move two steps instead of one
+    pub(crate) bonus_letters: BTreeSet<char>,
+    /// A letter which cannot be captured until `unlock_round`
+    pub(crate) locked_letter: Option<char>,
+    /// The first round (starting at 1) in which the locked letter can be captured
+    pub(crate) unlock_round: usize,
+    /// Captured letters can be stolen by pushing them past the opposite threshold
+    pub(crate) steal: bool,
+    /// A side gives up one of its captured letters instead of losing a letter to the opponent. The
+    /// alphabetically first captured letter is given up, returning to zero, and the letter it saves
+    /// is held back at the opponent's capture threshold.
+    pub(crate) sacrifice: bool,
+    /// The capture threshold, head start and captured letters of player one
+    pub(crate) player_one_handicap: Handicap,
+    /// The capture threshold, head start and captured letters of player two
+    pub(crate) player_two_handicap: Handicap,
+    /// How many hints each player may ask for during a game
+    pub(crate) max_hints: usize,
+    /// How many letters a side must capture to win the game
+    pub(crate) letters_to_win: usize,
+}
+
+const ALPHABET: &str = "bcdfghjklmnpqrstvwxyz";
+const VOWELS: &str = "aeiou";
+const DEFAULT_CAPTURE_THRESHOLD: i32 = 2;
+/// The highest capture threshold allowed, keeping board arithmetic far from overflowing
+const MAX_CAPTURE_THRESHOLD: i32 = 100;
+/// The furthest a letter can be from zero in a restored board. A locked letter can be pushed far
+/// past the thresholds over many rounds, but never near this.
+const MAX_BOARD_VALUE: i32 = 1_000_000;
+const DEFAULT_UNLOCK_ROUND: usize = 5;
+const DEFAULT_MAX_HINTS: usize = 3;
+/// A majority of the letters on the board
+const DEFAULT_LETTERS_TO_WIN: usize = 11;
+/// How many ranked answers a hint contains
+const HINT_ANSWERS: usize = 3;
+/// How many steps of board position a captured letter is worth when scoring
+const CAPTURE_WEIGHT: i32 = 10;
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            neutral_vowels: false,
+            bonus_letters: BTreeSet::new(),
+            locked_letter: None,
+            unlock_round: DEFAULT_UNLOCK_ROUND,
+            steal: false,
+            sacrifice: false,
+            player_one_handicap: Handicap::default(),
+            player_two_handicap: Handicap::default(),
+            max_hints: DEFAULT_MAX_HINTS,
+            letters_to_win: DEFAULT_LETTERS_TO_WIN,
+        }
+    }
+}
+
+fn letters(letters: &BTreeSet<char>) -> String {
+    letters.iter().map(char::to_string).collect::<Vec<_>>().join(", ")
+}
+
+impl Rules {
+    /// A description of each rule which differs from the defaults
+    pub(crate) fn summary(&self) -> Vec<String> {
+        let mut summary = Vec::new();
+        if self.neutral_vowels {
+            summary.push(String::from("neutral vowels"));
+        }
+        if !self.bonus_letters.is_empty() {
+            summary.push(format!("bonus letters: {}", letters(&self.bonus_letters)));
+        }
+        if let Some(letter) = self.locked_letter {
+            summary.push(format!("{} locked until round {}", letter, self.unlock_round));
+        }
+        if self.steal {
+            summary.push(String::from("steal"));
+        }
+        if self.sacrifice {
+            summary.push(String::from("sacrifice"));
+        }
+        if self.letters_to_win != DEFAULT_LETTERS_TO_WIN {
+            summary.push(format!("{} letters to win", self.letters_to_win));
+        }
+        let default_handicap = Handicap::default();
+        for (name, handicap) in [
+            ("player one", &self.player_one_handicap),
+            ("player two", &self.player_two_handicap),
+        ] {
+            if *handicap != default_handicap {
+                summary.push(format!("{} handicap", name));
+            }
+        }
+        summary
+    }
+
+    /// The same rules with the sides swapped
+    fn swapped(&self) -> Self {
+        Self {
+            player_one_handicap: self.player_two_handicap.clone(),
+            player_two_handicap: self.player_one_handicap.clone(),
+            ..self.clone()
+        }
+    }
+
+    pub(crate) fn handicap(&self, side: Side) -> &Handicap {
+        match side {
+            Side::PlayerOne => &self.player_one_handicap,
+            Side::PlayerTwo => &self.player_two_handicap,
+        }
+    }
+
+    /// Check the rules can be played: every letter named is on the board and no letter starts
+    /// captured by both sides or within a step of being captured
+    pub(crate) fn validate(&self) -> Result<()> {
+        let on_board = |letter: &char| ALPHABET.contains(*letter);
+        if !self.bonus_letters.iter().all(on_board) || !self.locked_letter.iter().all(on_board) {
+            return Err(Error::InvalidRules);
+        }
+        if self.letters_to_win == 0 || self.letters_to_win > ALPHABET.len() {
+            return Err(Error::InvalidRules);
+        }
+        for handicap in [&self.player_one_handicap, &self.player_two_handicap] {
+            let threshold = handicap.capture_threshold;
+            if !(0..=MAX_CAPTURE_THRESHOLD).contains(&threshold)
+                || !handicap.offsets.keys().all(on_board)
+                || !handicap.captured.iter().all(on_board)
+                || handicap.offsets.values().any(|offset| *offset <= -threshold || *offset >= threshold)
+            {
+                return Err(Error::InvalidRules);
+            }
+        }
+        // Offsets from both sides add up, so check where each letter starts too. Both are within a
+        // capture threshold by now so cannot overflow.
+        for letter in ALPHABET.chars() {
+            let offset = |handicap: &Handicap| handicap.offsets.get(&letter).copied().unwrap_or(0);
+            let start = offset(&self.player_one_handicap) - offset(&self.player_two_handicap);
+            if start >= self.player_one_handicap.capture_threshold
+                || -start >= self.player_two_handicap.capture_threshold
+            {
+                return Err(Error::InvalidRules);
+            }
+        }
+        if !self.player_one_handicap.captured.is_disjoint(&self.player_two_handicap.captured) {
+            return Err(Error::InvalidRules);
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Default, Deserialize, Serialize, Debug)]
 pub(crate) struct Board {
     pub(crate) board: BTreeMap<char, i32>,
     pub(crate) player_one_captured: BTreeSet<char>,
     pub(crate) player_two_captured: BTreeSet<char>,
+    pub(crate) rules: Rules,
 }
 
 fn make_start_map() -> BTreeMap<char, i32> {
     let mut letter_to_number: BTreeMap<char, i32> = BTreeMap::new();
-    let alphabet = "bcdfghjklmnpqrstvwxyz";
-    for letter in alphabet.chars() {
+    for letter in ALPHABET.chars() {
         letter_to_number.insert(letter, 0);
     }
     letter_to_number
 }
 
 impl Board {
-    pub(crate) fn new() -> Self {
-        Self {
+    pub(crate) fn new(rules: Rules) -> Self {
+        let mut board = Self {
             board: make_start_map(),
             player_one_captured: BTreeSet::new(),
             player_two_captured: BTreeSet::new(),
+            rules,
+        };
+        for side in [Side::PlayerOne, Side::PlayerTwo] {
+            let handicap = board.rules.handicap(side).clone();
+            for (letter, offset) in handicap.offsets {
+                if let Some(value) = board.board.get_mut(&letter) {
+                    *value += offset * side.direction();
+                }
+            }
+            for letter in handicap.captured {
+                board.board.insert(letter, handicap.capture_threshold.saturating_add(1) * side.direction());
+                board.captured_mut(side).insert(letter);
+            }
+        }
+        board
+    }
+
+    /// Check the board could have come from play under its rules: every letter is on it, none is
+    /// captured by both sides, uncaptured letters are within the capture thresholds and captured
+    /// ones have not been pushed past the opponent's
+    pub(crate) fn validate(&self) -> Result<()> {
+        self.rules.validate()?;
+        let letters_match = self.board.keys().copied().eq(ALPHABET.chars());
+        let captured_valid = self.player_one_captured.is_disjoint(&self.player_two_captured)
+            && self
+                .player_one_captured
+                .iter()
+                .chain(&self.player_two_captured)
+                .all(|letter| self.board.contains_key(letter));
+        let (one, two) = (self.threshold(Side::PlayerOne), self.threshold(Side::PlayerTwo));
+        let values_valid = self.board.iter().all(|(letter, value)| {
+            let in_range = match self.captured_by(letter) {
+                // The locked letter is only captured once it unlocks
+                None if self.rules.locked_letter == Some(*letter) => true,
+                None => (-two..=one).contains(value),
+                Some(Side::PlayerOne) => *value >= -two,
+                Some(Side::PlayerTwo) => *value <= one,
+            };
+            in_range && (-MAX_BOARD_VALUE..=MAX_BOARD_VALUE).contains(value)
+        });
+        if letters_match && captured_valid && values_valid {
+            Ok(())
+        } else {
+            Err(Error::InvalidExport)
+        }
+    }
+
+    fn threshold(&self, side: Side) -> i32 {
+        self.rules.handicap(side).capture_threshold
+    }
+
+    pub(crate) fn captured(&self, side: Side) -> &BTreeSet<char> {
+        match side {
+            Side::PlayerOne => &self.player_one_captured,
+            Side::PlayerTwo => &self.player_two_captured,
         }
     }
 
-    pub(crate) fn move_board(&mut self, player_one_answer: String, player_two_answer: String) -> () {
-        for letter in player_one_answer.chars() {
-            if self.board.contains_key(&letter) && !self.player_one_captured.contains(&letter) && !self.player_two_captured.contains(&letter) {
-                match self.board.entry(letter) {
-                    std::collections::btree_map::Entry::Occupied(mut entry) => {
-                            entry.insert(entry.get() + 1);
-                    },
-                    std::collections::btree_map::Entry::Vacant(_) => (),
+    fn captured_mut(&mut self, side: Side) -> &mut BTreeSet<char> {
+        match side {
+            Side::PlayerOne => &mut self.player_one_captured,
+            Side::PlayerTwo => &mut self.player_two_captured,
+        }
+    }
+
+    /// The side which has captured `letter`, if any
+    fn captured_by(&self, letter: &char) -> Option<Side> {
+        if self.player_one_captured.contains(letter) {
+            Some(Side::PlayerOne)
+        } else if self.player_two_captured.contains(letter) {
+            Some(Side::PlayerTwo)
+        } else {
+            None
+        }
+    }
+
+    /// Whether `side` is allowed to push `letter`
+    fn can_push(&self, side: Side, letter: &char) -> bool {
+        match self.captured_by(letter) {
+            None => true,
+            // Only the opponent of the capturing side can contest a captured letter
+            Some(owner) => self.rules.steal && owner != side,
+        }
+    }
+
+    /// Move the board for one side's answer
+    fn push(&mut self, answer: &str, side: Side) {
+        for letter in answer.chars() {
+            if self.rules.neutral_vowels && VOWELS.contains(letter) {
+                self.push_back(side);
+            } else if self.board.contains_key(&letter) && self.can_push(side, &letter) {
+                let step = if self.rules.bonus_letters.contains(&letter) { 2 } else { 1 };
+                if let Some(value) = self.board.get_mut(&letter) {
+                    *value += step * side.direction();
                 }
             }
-        } 
-
-        for letter in player_two_answer.chars() {
-            if self.board.contains_key(&letter) && !self.player_one_captured.contains(&letter) && !self.player_two_captured.contains(&letter) {
-                match self.board.entry(letter) {
-                    std::collections::btree_map::Entry::Occupied(mut entry) => {
-                            entry.insert(entry.get() - 1);
-                    },
-                    std::collections::btree_map::Entry::Vacant(_) => (),
-                }
+        }
+    }
+
+    /// Move the uncaptured letter the opponent of `side` leads on most one step back toward zero
+    fn push_back(&mut self, side: Side) {
+        let direction = side.direction();
+        let leading = self
+            .board
+            .iter()
+            .filter(|(letter, value)| self.captured_by(letter).is_none() && **value * direction < 0)
+            .max_by_key(|(letter, value)| (value.abs(), std::cmp::Reverse(**letter)))
+            .map(|(letter, _)| *letter);
+        if let Some(value) = leading.and_then(|letter| self.board.get_mut(&letter)) {
+            *value += direction;
+        }
+    }
+
+    /// The side which has pushed `letter` past its capture threshold, if any
+    fn past_threshold(&self, letter: &char) -> Option<Side> {
+        let value = self.board[letter];
+        if value > self.threshold(Side::PlayerOne) {
+            Some(Side::PlayerOne)
+        } else if value < -self.threshold(Side::PlayerTwo) {
+            Some(Side::PlayerTwo)
+        } else {
+            None
+        }
+    }
+
+    /// Move `letter` to `to`, recording the move in `deltas`
+    fn set(&mut self, letter: char, to: i32, deltas: &mut Vec<BoardDelta>) {
+        if let Some(from) = self.board.insert(letter, to).filter(|from| *from != to) {
+            deltas.push(BoardDelta::Moved { letter, from, to });
+        }
+    }
+
+    /// Capture `letter` for `side`, letting the opponent sacrifice its alphabetically first captured
+    /// letter instead if the rules allow
+    fn capture(&mut self, letter: char, side: Side, deltas: &mut Vec<BoardDelta>) {
+        let defender = side.opponent();
+        let sacrificed = if self.rules.sacrifice {
+            self.captured(defender).iter().next().copied()
+        } else {
+            None
+        };
+        match sacrificed {
+            Some(sacrificed) => {
+                self.captured_mut(defender).remove(&sacrificed);
+                self.set(sacrificed, 0, deltas);
+                self.set(letter, self.threshold(side) * side.direction(), deltas);
+                deltas.push(BoardDelta::Sacrificed {
+                    letter: sacrificed,
+                    side: defender,
+                    saved: letter,
+                });
+            }
+            None => {
+                self.captured_mut(side).insert(letter);
+                deltas.push(BoardDelta::Captured { letter, side });
             }
         }
+    }
+
+    /// The side which has won, either by capturing enough letters or by holding more letters once
+    /// every letter is captured
+    pub(crate) fn winner(&self) -> Option<Side> {
+        let one = self.player_one_captured.len();
+        let two = self.player_two_captured.len();
+        if one >= self.rules.letters_to_win {
+            Some(Side::PlayerOne)
+        } else if two >= self.rules.letters_to_win {
+            Some(Side::PlayerTwo)
+        } else if one + two < self.board.len() || one == two {
+            None
+        } else if one > two {
+            Some(Side::PlayerOne)
+        } else {
+            Some(Side::PlayerTwo)
+        }
+    }
+
+    /// Whether no more rounds can be played
+    pub(crate) fn is_over(&self) -> bool {
+        self.winner().is_some()
+            || self.player_one_captured.len() + self.player_two_captured.len() == self.board.len()
+    }
+
+    /// How far ahead `side` is, counting each captured letter as `CAPTURE_WEIGHT` steps
+    pub(crate) fn advantage(&self, side: Side) -> i32 {
+        let captured = self.captured(side).len() as i32 - self.captured(side.opponent()).len() as i32;
+        let position = self
+            .board
+            .iter()
+            .filter(|(letter, _)| self.captured_by(letter).is_none())
+            .map(|(_, value)| value * side.direction())
+            .sum::<i32>();
+        captured * CAPTURE_WEIGHT + position
+    }
+
+    /// The change in `side`'s advantage if it answered `answer` in `round` and the opponent gave
+    /// an empty answer
+    pub(crate) fn projected_gain(&self, round: usize, side: Side, answer: &str) -> i32 {
+        let (board, _) = self.project(round, side, answer);
+        board.advantage(side) - self.advantage(side)
+    }
+
+    /// The board and its changes if `side` answered `answer` in `round` and the opponent gave an
+    /// empty answer
+    fn project(&self, round: usize, side: Side, answer: &str) -> (Board, Vec<BoardDelta>) {
+        let mut board = self.clone();
+        let (player_one_answer, player_two_answer) = match side {
+            Side::PlayerOne => (answer, ""),
+            Side::PlayerTwo => ("", answer),
+        };
+        let deltas = board.move_board(round, player_one_answer.to_string(), player_two_answer.to_string());
+        (board, deltas)
+    }
+
+    /// Move the board for a completed round. `round` is the number of the round starting at 1.
+    /// Returns the changes made to the board.
+    pub(crate) fn move_board(&mut self, round: usize, player_one_answer: String, player_two_answer: String) -> Vec<BoardDelta> {
+        let before = self.board.clone();
+        self.push(&player_one_answer, Side::PlayerOne);
+        self.push(&player_two_answer, Side::PlayerTwo);
 
-        for (key, value) in &self.board {
-            if !self.player_one_captured.contains(key) && !self.player_two_captured.contains(key) {
-                if value > &2 {
-                    self.player_one_captured.insert(*key);
-                } else if value < &-2 {
-                    self.player_two_captured.insert(*key);
+        let mut deltas = before
+            .iter()
+            .filter(|(letter, from)| self.board[*letter] != **from)
+            .map(|(letter, from)| BoardDelta::Moved {
+                letter: *letter,
+                from: *from,
+                to: self.board[letter],
+            })
+            .collect::<Vec<_>>();
+
+        let letters = self.board.keys().copied().collect::<Vec<_>>();
+        for key in letters {
+            if self.rules.locked_letter == Some(key) && round < self.rules.unlock_round {
+                continue;
+            }
+            let side = match self.past_threshold(&key) {
+                Some(side) => side,
+                None => continue,
+            };
+            match self.captured_by(&key) {
+                None => self.capture(key, side, &mut deltas),
+                Some(owner) if owner != side && self.rules.steal => {
+                    self.captured_mut(owner).remove(&key);
+                    self.captured_mut(side).insert(key);
+                    deltas.push(BoardDelta::Stolen { letter: key, side });
                 }
+                Some(_) => (),
             }
         }
+        deltas
+    }
+}
+
+/// A best-of-N series of games between the same players
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub(crate) struct Series {
+    /// The number of games in the series
+    pub(crate) best_of: usize,
+    /// The winner of each finished game in the series, `None` for a draw
+    pub(crate) results: Vec<Option<Player>>,
+    /// The number of games each player has won
+    pub(crate) wins: BTreeMap<Player, usize>,
+    /// The player who has won a majority of the series, if any
+    pub(crate) winner: Option<Player>,
+}
 
+impl Default for Series {
+    fn default() -> Self {
+        Self::new(default_best_of())
+    }
+}
+
+impl Series {
+    fn new(best_of: usize) -> Self {
+        Self {
+            best_of,
+            results: Vec::new(),
+            wins: BTreeMap::new(),
+            winner: None,
+        }
+    }
+
+    /// Credit `player`'s results to `name` instead
+    fn rename(&mut self, player: &Player, name: &Player) {
+        for result in self.results.iter_mut().chain([&mut self.winner]) {
+            if result.as_ref() == Some(player) {
+                *result = Some(name.clone());
+            }
+        }
+        if let Some(wins) = self.wins.remove(player) {
+            self.wins.insert(name.clone(), wins);
+        }
+    }
+
+    fn record(&mut self, winner: Option<Player>) {
+        if let Some(winner) = &winner {
+            let wins = self.wins.entry(winner.clone()).or_default();
+            *wins += 1;
+            if *wins > self.best_of / 2 {
+                self.winner = Some(winner.clone());
+            }
+        }
+        self.results.push(winner);
+    }
+
+    /// The series for the next game, starting a new series once this one is decided
+    fn next(&self) -> Self {
+        if self.winner.is_some() || self.results.len() >= self.best_of {
+            Self::new(self.best_of)
+        } else {
+            self.clone()
+        }
+    }
+}
+
+/// Wakes clients long-polling a game when it changes
+#[derive(Default)]
+pub(crate) struct Notifier {
+    /// The latest version of the game and whether it has been deleted
+    state: Mutex<(u64, bool)>,
+    changed: Condvar,
+}
+
+impl Notifier {
+    fn notify(&self, version: u64) {
+        self.state.lock().0 = version;
+        self.changed.notify_all();
+    }
+
+    /// Wake every waiter for good, used when the game is deleted
+    fn close(&self) {
+        self.state.lock().1 = true;
+        self.changed.notify_all();
+    }
+
+    /// Block until the game is newer than `since`, is deleted or `timeout` passes
+    pub(crate) fn wait(&self, since: u64, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock();
+        while state.0 <= since && !state.1 {
+            if self.changed.wait_until(&mut state, deadline).timed_out() {
+                break;
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Notifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Notifier").finish_non_exhaustive()
     }
 }
 
@@ -237,6 +992,41 @@ pub(crate) struct Game {
     pub(crate) rounds: Vec<Round>,
     /// 
     pub(crate) board: Board,
+    /// The bot filling one of the seats, if any
+    pub(crate) bot: Option<Bot>,
+    /// The number of hints each player has used
+    pub(crate) hints_used: BTreeMap<Player, usize>,
+    /// Whether the game is listed in the lobby
+    pub(crate) public: bool,
+    /// Whether names and answers are run through the content filter
+    #[serde(default)]
+    pub(crate) family_mode: bool,
+    /// When the game was created in seconds since the Unix epoch
+    pub(crate) created_at: u64,
+    /// Whether the game has finished
+    pub(crate) finished: bool,
+    /// The player who won the game, `None` for a draw or an unfinished game
+    pub(crate) winner: Option<Player>,
+    /// The series this game is part of, including this game once it has finished
+    pub(crate) series: Series,
+    /// The game this game is a rematch of
+    pub(crate) previous_game: Option<String>,
+    /// The rematch of this game
+    pub(crate) next_game: Option<String>,
+    /// The profiles the players' results are recorded to
+    pub(crate) player_one_profile: Option<ProfileId>,
+    pub(crate) player_two_profile: Option<ProfileId>,
+    /// Whether the result has been recorded to the players' profiles
+    pub(crate) stats_recorded: bool,
+    /// Incremented whenever the game changes, missing from exports made before versions were added
+    #[serde(default)]
+    pub(crate) version: u64,
+    /// When the game last changed in seconds since the Unix epoch
+    #[serde(default)]
+    pub(crate) updated_at: u64,
+    /// Wakes long-polling clients whenever the game changes
+    #[serde(skip)]
+    notifier: Arc<Notifier>,
 }
 
 impl Game {
@@ -246,17 +1036,119 @@ impl Game {
             return Err(Error::RoundNotInStartState);
         }
         if self.player_one.is_none() {
+            logging::event("player_joined", &[("player", &player)]);
             self.player_one = Some(player);
-            Ok(())
         } else if self.player_two.is_none() {
+            logging::event("player_joined", &[("player", &player)]);
             self.player_two = Some(player);
-            Ok(())
         } else {
-            Err(Error::PlayerConflict)
+            return Err(Error::PlayerConflict);
         }
+        self.touch();
+        Ok(())
     }
 
-    pub(crate) fn answer(&mut self, answer: Answer, questions: State<'_, Mutex<QuestionLookup>>) -> Result<()> {
+    /// Record that the game has changed
+    fn touch(&mut self) {
+        self.version += 1;
+        self.updated_at = now();
+        self.notifier.notify(self.version);
+    }
+
+    /// The notifier to wait on outside the `Games` lock for the game to change
+    pub(crate) fn notifier(&self) -> Arc<Notifier> {
+        Arc::clone(&self.notifier)
+    }
+
+    /// An opaque tag which changes whenever the game changes
+    pub(crate) fn etag(&self) -> String {
+        format!("\"{}-{}\"", self.created_at, self.version)
+    }
+
+    pub(crate) fn add_bot(&mut self, bot: Bot) -> Result<()> {
+        if self.bot.is_some() || self.player_one.as_ref() == Some(&bot.name) {
+            return Err(Error::PlayerConflict);
+        }
+        self.add_player(bot.name.clone())?;
+        self.bot = Some(bot);
+        self.touch();
+        Ok(())
+    }
+
+    /// The side `player` is seated on
+    pub(crate) fn side(&self, player: &Player) -> Option<Side> {
+        if self.player_one.as_ref() == Some(player) {
+            Some(Side::PlayerOne)
+        } else if self.player_two.as_ref() == Some(player) {
+            Some(Side::PlayerTwo)
+        } else {
+            None
+        }
+    }
+
+    /// Answer the current round for the bot if it has not answered yet
+    pub(crate) fn bot_answer(&mut self, words: &WordLookup, questions: &QuestionLookup) -> Result<()> {
+        let bot = match &self.bot {
+            Some(bot) => bot.clone(),
+            None => return Ok(()),
+        };
+        let side = match self.side(&bot.name) {
+            Some(side) if self.player_one.is_some() && self.player_two.is_some() => side,
+            _ => return Ok(()),
+        };
+        let round = self.current_round();
+        if round.answer(side).is_some() {
+            return Ok(());
+        }
+        let words = words.get(round.question(side));
+        let answer = bot.choose(&self.board, self.rounds.len(), side, words);
+        self.answer(
+            Answer {
+                player: bot.name,
+                answer,
+            },
+            questions,
+        )
+    }
+
+    /// Rank candidate answers to `player`'s current question by how much they would gain on the board
+    pub(crate) fn hint(&mut self, player: &Player, words: &WordLookup) -> Result<Vec<Hint>> {
+        let side = self.side(player).ok_or(Error::PlayerNotFound)?;
+        let used = self.hints_used.get(player).copied().unwrap_or_default();
+        if used >= self.board.rules.max_hints {
+            return Err(Error::HintLimitReached);
+        }
+        let round = self.rounds.len();
+        let words = words.get(self.current_round().question(side)).iter().collect::<BTreeSet<_>>();
+        let mut hints = words
+            .into_iter()
+            .map(|word| {
+                let (board, deltas) = self.board.project(round, side, word);
+                let captures = deltas
+                    .iter()
+                    .filter_map(|delta| match delta {
+                        BoardDelta::Captured { letter, side: captured_by } if *captured_by == side => {
+                            Some(*letter)
+                        }
+                        BoardDelta::Stolen { letter, side: stolen_by } if *stolen_by == side => Some(*letter),
+                        _ => None,
+                    })
+                    .collect();
+                Hint {
+                    answer: word.clone(),
+                    gain: board.advantage(side) - self.board.advantage(side),
+                    captures,
+                }
+            })
+            .collect::<Vec<_>>();
+        hints.sort_by_key(|hint| std::cmp::Reverse(hint.gain));
+        hints.truncate(HINT_ANSWERS);
+        self.hints_used.insert(player.clone(), used + 1);
+        self.touch();
+        Ok(hints)
+    }
+
+    pub(crate) fn answer(&mut self, answer: Answer, questions: &QuestionLookup) -> Result<()> {
         let player = &answer.player;
         // Confirm the player exists
         let (player_one, player_two) = match (self.player_one.clone(), self.player_two.clone()) {
@@ -268,6 +1160,9 @@ impl Game {
         if &player_one != player && &player_two != player {
             return Err(Error::PlayerNotFound);
         }
+        if self.finished {
+            return Err(Error::GameOver);
+        }
         // Confirm we are collecting answers for the current round
         let state = self.current_round_state();
         if state != RoundState::Start && self.current_round_state() != RoundState::CollectingAnswers
@@ -275,6 +1170,10 @@ impl Game {
             return Err(Error::RoundNotInCollectingAnswersState);
         }
 
+        logging::event(
+            "answered",
+            &[("player", player), ("round", &self.rounds.len()), ("length", &answer.answer.chars().count())],
+        );
         let current_round = self.current_round_mut();
         // Add or replace the answer
         if player == &player_one  {
@@ -284,29 +1183,150 @@ impl Game {
         }
 
         if current_round.player_one_answer.is_some() && current_round.player_two_answer.is_some() {
-            let x = questions.lock().get();
-            let y = questions.lock().get();
+            let x = questions.get();
+            let y = questions.get();
             self.add_round_if_complete(x, y);
         }
-        
+        self.touch();
         Ok(())
     }
 
     pub(crate) fn add_round_if_complete(&mut self, question_one: String, question_two: String) {
         if self.current_round_state() == RoundState::Complete {
-            self.board.move_board(self.current_round().player_one_answer.as_ref().unwrap().to_string(),self.current_round().player_two_answer.as_ref().unwrap().to_string());
-            self.add_round(question_one, question_two);
+            let round = self.rounds.len();
+            let deltas = self.board.move_board(round, self.current_round().player_one_answer.as_ref().unwrap().to_string(),self.current_round().player_two_answer.as_ref().unwrap().to_string());
+            for delta in &deltas {
+                match delta {
+                    BoardDelta::Captured { letter, side } => {
+                        logging::event("captured", &[("letter", letter), ("side", &format!("{:?}", side))])
+                    }
+                    BoardDelta::Stolen { letter, side } => {
+                        logging::event("stolen", &[("letter", letter), ("side", &format!("{:?}", side))])
+                    }
+                    _ => (),
+                }
+            }
+            logging::event("round_completed", &[("round", &round)]);
+            self.current_round_mut().deltas = deltas;
+            if self.board.is_over() {
+                self.finish();
+            } else {
+                self.add_round(question_one, question_two);
+            }
+            self.touch();
         }
     }
 
+    /// Complete the current round as if the players who have not answered gave empty answers, for
+    /// rounds stuck waiting on a player who has gone
+    pub(crate) fn force_advance(&mut self, questions: &QuestionLookup) -> Result<()> {
+        if self.finished {
+            return Err(Error::GameOver);
+        }
+        if self.player_one.is_none() || self.player_two.is_none() {
+            return Err(Error::PlayerNotFound);
+        }
+        let round = self.rounds.len();
+        let current_round = self.current_round_mut();
+        current_round.player_one_answer.get_or_insert_with(String::new);
+        current_round.player_two_answer.get_or_insert_with(String::new);
+        logging::event("round_forced", &[("round", &round)]);
+        self.add_round_if_complete(questions.get(), questions.get());
+        Ok(())
+    }
+
+    /// Replace `player`'s answer to `round`, counting from 1. Clearing an answer to the current
+    /// round lets the player answer again while answers to completed rounds, which have already
+    /// moved the board, are replaced with a placeholder.
+    pub(crate) fn moderate_answer(&mut self, round: usize, player: &Player, answer: Option<String>) -> Result<()> {
+        let side = self.side(player).ok_or(Error::PlayerNotFound)?;
+        let index = round.checked_sub(1).filter(|index| *index < self.rounds.len()).ok_or(Error::RoundNotFound)?;
+        let round = &mut self.rounds[index];
+        let complete = round.state() == RoundState::Complete;
+        let slot = match side {
+            Side::PlayerOne => &mut round.player_one_answer,
+            Side::PlayerTwo => &mut round.player_two_answer,
+        };
+        if slot.is_none() {
+            return Err(Error::AnswerNotFound);
+        }
+        *slot = match answer {
+            Some(answer) => Some(answer),
+            None if complete => Some(String::from(REMOVED_ANSWER)),
+            None => None,
+        };
+        logging::event("answer_moderated", &[("player", player), ("round", &(index + 1))]);
+        self.touch();
+        Ok(())
+    }
+
+    /// Rename `player` throughout the game, to a placeholder for their side if `name` is `None`
+    pub(crate) fn rename_player(&mut self, player: &Player, name: Option<Player>) -> Result<()> {
+        let side = self.side(player).ok_or(Error::PlayerNotFound)?;
+        let name = name.unwrap_or_else(|| match side {
+            Side::PlayerOne => Player::from("Player One"),
+            Side::PlayerTwo => Player::from("Player Two"),
+        });
+        if &name != player && self.side(&name).is_some() {
+            return Err(Error::PlayerConflict);
+        }
+        for seat in [&mut self.player_one, &mut self.player_two, &mut self.winner] {
+            if seat.as_ref() == Some(player) {
+                *seat = Some(name.clone());
+            }
+        }
+        if let Some(bot) = self.bot.as_mut().filter(|bot| &bot.name == player) {
+            bot.name = name.clone();
+        }
+        if let Some(hints) = self.hints_used.remove(player) {
+            self.hints_used.insert(name.clone(), hints);
+        }
+        self.series.rename(player, &name);
+        logging::event("player_renamed", &[("player", player), ("name", &name)]);
+        self.touch();
+        Ok(())
+    }
+
+    fn summary(&self, game_id: String, now: u64) -> GameSummary {
+        GameSummary {
+            game_id,
+            player_one: self.player_one.clone(),
+            player_two: self.player_two.clone(),
+            bot: self.bot.is_some(),
+            rounds: self.rounds.len(),
+            player_one_captured: self.board.captured(Side::PlayerOne).len(),
+            player_two_captured: self.board.captured(Side::PlayerTwo).len(),
+            finished: self.finished,
+            version: self.version,
+            idle: now.saturating_sub(self.created_at.max(self.updated_at)),
+        }
+    }
+
+    fn finish(&mut self) {
+        self.finished = true;
+        self.winner = match self.board.winner() {
+            Some(Side::PlayerOne) => self.player_one.clone(),
+            Some(Side::PlayerTwo) => self.player_two.clone(),
+            None => None,
+        };
+        logging::event("game_over", &[("winner", &self.winner.as_deref().unwrap_or("draw"))]);
+        self.series.record(self.winner.clone());
+    }
+
     fn add_round(&mut self, question_one: String, question_two: String) {
         self.rounds.push(Round::new(question_one, question_two));
     }
 
-    // pub(crate) fn previous_round(&self) -> Option<&Round> {
-    //     let index = self.rounds.len() - 2;
-    //     self.rounds.get(index)
-    // }
+    #[cfg(test)]
+    pub(crate) fn previous_round(&self) -> Option<&Round> {
+        let index = self.rounds.len().checked_sub(2)?;
+        self.rounds.get(index)
+    }
+
+    /// The number of rounds both players have answered
+    pub(crate) fn completed_rounds(&self) -> usize {
+        self.rounds.iter().filter(|round| round.state() == RoundState::Complete).count()
+    }
 
     pub(crate) fn current_round(&self) -> &Round {
         let index = self.rounds.len() - 1;
@@ -323,8 +1343,8 @@ impl Game {
         round.state()
     }
 
-    fn init_board(&mut self) -> () {
-        self.board = Board::new();
+    fn init_board(&mut self, rules: Rules) {
+        self.board = Board::new(rules);
     }
 
     // pub fn change_question(&mut self, new_question: String) -> () {
@@ -335,35 +1355,350 @@ impl Game {
     // }
 }
 
+/// A game shared between requests, locked on its own so games do not block each other
+pub(crate) type SharedGame = Arc<Mutex<Game>>;
+
+/// Every game on the server. A game's lock may be held while taking the `games` or `profiles`
+/// lock but never the other way round.
 #[derive(Default)]
-pub(crate) struct Games(HashMap<String, Game>);
+pub(crate) struct Games {
+    games: RwLock<HashMap<String, SharedGame>>,
+    pub(crate) profiles: Mutex<Profiles>,
+    /// The rules for games created without any
+    pub(crate) default_rules: Rules,
+    pub(crate) metrics: Metrics,
+    /// Set once the server starts shutting down, after which no games are created
+    shutting_down: AtomicBool,
+    /// The maintenance notice shown to every player, if any
+    pub(crate) notice: Mutex<Option<Notice>>,
+    /// Blocked words for family mode games
+    pub(crate) filter: ContentFilter,
+    /// Whether games are in family mode unless created otherwise
+    pub(crate) family_mode: bool,
+    /// Names and answers let through by the filter for moderators to review, the oldest dropped
+    /// once there are `MAX_FLAGS`
+    pub(crate) flags: Mutex<VecDeque<Flag>>,
+    /// The limits imported games are checked against however they arrive, whether from a request,
+    /// a snapshot or the command line
+    pub(crate) limits: InputLimits,
+}
+
+/// How many generated codes to try before giving up on finding an unused one
+const CODE_ATTEMPTS: usize = 32;
+
+/// How many flags are kept for review
+pub(crate) const MAX_FLAGS: usize = 1000;
 
 impl Games {
-    #[allow(clippy::map_entry)]
+    /// Pick an unused game id, either the requested `vanity` id or a generated code
+    pub(crate) fn allocate_id(&self, vanity: Option<String>) -> Result<String> {
+        let games = self.games.read();
+        if let Some(game_id) = vanity {
+            let game_id = game_code::normalize(&game_id);
+            return if games.contains_key(&game_id) {
+                Err(Error::GameConflict)
+            } else {
+                Ok(game_id)
+            };
+        }
+        let mut rng = rand::thread_rng();
+        (0..CODE_ATTEMPTS)
+            .map(|_| game_code::generate(&mut rng))
+            .find(|code| !games.contains_key(code))
+            .ok_or(Error::GameConflict)
+    }
+
+    /// Add a game under `game_id` unless the id is already taken
+    fn insert(&self, game_id: String, game: Game) -> Result<()> {
+        if self.is_shutting_down() {
+            return Err(Error::ShuttingDown);
+        }
+        match self.games.write().entry(game_code::normalize(&game_id)) {
+            Entry::Occupied(_) => Err(Error::GameConflict),
+            Entry::Vacant(entry) => {
+                logging::event("game_created", &[("new_game", entry.key())]);
+                self.metrics.game_created(entry.key());
+                entry.insert(Arc::new(Mutex::new(game)));
+                Ok(())
+            }
+        }
+    }
+
     pub(crate) fn create(
-        &mut self,
+        &self,
         game_id: String,
-        initial_player: Player,
+        data: CreateGameData,
         initial_question: String,
         initial_question_two: String,
     ) -> Result<()> {
-        if self.0.contains_key(&game_id) {
-            Err(Error::GameConflict)
-        } else {
-            let mut game = Game::default();
-            game.add_round(initial_question, initial_question_two);
-            game.add_player(initial_player)?;
-            game.init_board();
-            self.0.insert(game_id, game);
-            Ok(())
+        let rules = data.rules.unwrap_or_else(|| self.default_rules.clone());
+        rules.validate()?;
+        if data.best_of == 0 {
+            return Err(Error::InvalidRules);
+        }
+        if let Some(profile) = &data.profile {
+            self.profiles.lock().get(profile)?;
+        }
+        let family_mode = data.family_mode.unwrap_or(self.family_mode);
+        let player = self.screen(Some(&game_id), family_mode, &data.player, Field::Name, data.player.clone())?;
+        let mut game = Game::default();
+        game.add_round(initial_question, initial_question_two);
+        game.add_player(player)?;
+        game.init_board(rules);
+        game.public = data.public;
+        game.family_mode = family_mode;
+        game.created_at = now();
+        game.series = Series::new(data.best_of);
+        game.player_one_profile = data.profile;
+        self.insert(game_id, game)
+    }
+
+    /// Start a new game between the players of a finished game with the sides swapped, returning
+    /// the id of the new game. Asking again returns the same game.
+    pub(crate) fn rematch(
+        &self,
+        game_id: &str,
+        initial_question: String,
+        initial_question_two: String,
+    ) -> Result<String> {
+        let previous = self.get(game_id)?;
+        let mut previous = previous.lock();
+        if let Some(next_game) = &previous.next_game {
+            return Ok(next_game.clone());
+        }
+        if !previous.finished {
+            return Err(Error::GameNotOver);
+        }
+        let mut game = Game::default();
+        game.add_round(initial_question, initial_question_two);
+        game.player_one = previous.player_two.clone();
+        game.player_two = previous.player_one.clone();
+        game.bot = previous.bot.clone();
+        game.family_mode = previous.family_mode;
+        game.player_one_profile = previous.player_two_profile.clone();
+        game.player_two_profile = previous.player_one_profile.clone();
+        game.init_board(previous.board.rules.swapped());
+        game.series = previous.series.next();
+        game.previous_game = Some(game_id.to_string());
+        game.created_at = now();
+        let next_game = self.allocate_id(None)?;
+        self.insert(next_game.clone(), game)?;
+        logging::event("rematch", &[("next_game", &next_game)]);
+        previous.next_game = Some(next_game.clone());
+        previous.touch();
+        Ok(next_game)
+    }
+
+    /// The public games with an open seat, oldest first
+    pub(crate) fn lobby(&self) -> Vec<LobbyGame> {
+        let now = now();
+        let mut lobby = self
+            .all()
+            .into_iter()
+            .filter_map(|(game_id, game)| {
+                let game = game.lock();
+                if !game.public {
+                    return None;
+                }
+                let players = game.player_one.iter().chain(&game.player_two).cloned().collect::<Vec<_>>();
+                Some(LobbyGame {
+                    game_id,
+                    open_seats: 2 - players.len(),
+                    players,
+                    rules: game.board.rules.summary(),
+                    age: now.saturating_sub(game.created_at),
+                })
+            })
+            .filter(|game| game.open_seats > 0)
+            .collect::<Vec<_>>();
+        lobby.sort_by(|a, b| b.age.cmp(&a.age).then_with(|| a.game_id.cmp(&b.game_id)));
+        lobby
+    }
+
+    /// Seat a player in `game`
+    pub(crate) fn join(&self, game_id: &str, game: &mut Game, data: PlayerData) -> Result<()> {
+        if let Some(profile) = &data.profile {
+            self.profiles.lock().get(profile)?;
+            // A profile playing itself could be rated for a result it chose
+            if [&game.player_one_profile, &game.player_two_profile].contains(&&data.profile) {
+                return Err(Error::ProfileConflict);
+            }
+        }
+        self.screen(Some(game_id), game.family_mode, &data.player, Field::Name, data.player.clone())?;
+        game.add_player(data.player.clone())?;
+        match game.side(&data.player) {
+            Some(Side::PlayerOne) => game.player_one_profile = data.profile,
+            Some(Side::PlayerTwo) => game.player_two_profile = data.profile,
+            None => (),
+        }
+        Ok(())
+    }
+
+    /// Seat a bot in `game`
+    pub(crate) fn add_bot(&self, game_id: &str, game: &mut Game, bot: Bot) -> Result<()> {
+        self.screen(Some(game_id), game.family_mode, &bot.name, Field::Name, bot.name.clone())?;
+        game.add_bot(bot)
+    }
+
+    /// Give an answer in `game`
+    pub(crate) fn answer(&self, game_id: &str, game: &mut Game, answer: Answer, questions: &QuestionLookup) -> Result<()> {
+        let text = self.screen(Some(game_id), game.family_mode, &answer.player, Field::Answer, answer.answer)?;
+        game.answer(Answer::new(&answer.player, &text), questions)
+    }
+
+    /// Check a name or answer against the content filter if `family_mode`, returning the text to
+    /// use in its place
+    pub(crate) fn screen(
+        &self,
+        game_id: Option<&str>,
+        family_mode: bool,
+        player: &Player,
+        field: Field,
+        text: String,
+    ) -> Result<String> {
+        if !family_mode {
+            return Ok(text);
+        }
+        let masked = match self.filter.mask(&text) {
+            Some(masked) => masked,
+            None => return Ok(text),
+        };
+        match (self.filter.mode, field) {
+            (FilterMode::Mask, Field::Answer) => Ok(masked),
+            (FilterMode::Flag, _) => {
+                logging::event("content_flagged", &[("player", player), ("field", &field)]);
+                let mut flags = self.flags.lock();
+                // A name flagged in the matchmaking queue is flagged again once the player is
+                // matched, so attach the game to the earlier flag
+                let queued = flags.iter_mut().find(|flag| {
+                    flag.game_id.is_none() && &flag.player == player && flag.field == field && flag.text == text
+                });
+                match queued {
+                    Some(flag) => flag.game_id = game_id.map(String::from),
+                    None => {
+                        if flags.len() == MAX_FLAGS {
+                            flags.pop_front();
+                        }
+                        flags.push_back(Flag {
+                            game_id: game_id.map(String::from),
+                            player: player.clone(),
+                            field,
+                            text: text.clone(),
+                            at: now(),
+                        })
+                    }
+                }
+                Ok(text)
+            }
+            _ => Err(Error::InappropriateContent),
+        }
+    }
+
+    /// Record the result of a finished game to the players' profiles, once
+    pub(crate) fn record_stats(&self, game: &mut Game) {
+        if !game.finished || game.stats_recorded {
+            return;
+        }
+        game.stats_recorded = true;
+        game.touch();
+        let mut profiles = self.profiles.lock();
+        for (side, profile) in [
+            (Side::PlayerOne, &game.player_one_profile),
+            (Side::PlayerTwo, &game.player_two_profile),
+        ] {
+            if let Some(profile) = profile {
+                let result = GameResult {
+                    won: game.board.winner() == Some(side),
+                    letters_captured: game.board.captured(side).len(),
+                    answers: game.rounds.iter().filter_map(|round| round.answer(side)).map(String::as_str).collect(),
+                };
+                profiles.record(profile, &result);
+            }
         }
+        if let (Some(one), Some(two)) = (&game.player_one_profile, &game.player_two_profile) {
+            let score = match game.board.winner() {
+                Some(Side::PlayerOne) => 1.0,
+                Some(Side::PlayerTwo) => 0.0,
+                None => 0.5,
+            };
+            let margin = game.board.player_one_captured.len().abs_diff(game.board.player_two_captured.len());
+            profiles.rate(one, two, score, margin, now());
+        }
+    }
+
+    pub(crate) fn export(&self, game_id: &str) -> Result<GameExport> {
+        let game = self.get(game_id)?;
+        let game = game.lock();
+        Ok(GameExport::new(game_id, &game))
+    }
+
+    /// Restore an exported game under its original id
+    pub(crate) fn import(&self, export: GameExport) -> Result<()> {
+        export.validate(&self.limits)?;
+        self.insert(export.game_id, export.game)
+    }
+
+    /// The number of games held
+    pub(crate) fn len(&self) -> usize {
+        self.games.read().len()
+    }
+
+    /// The number of games held, or `None` if they could not be read within `timeout`
+    pub(crate) fn try_len(&self, timeout: Duration) -> Option<usize> {
+        self.games.try_read_for(timeout).map(|games| games.len())
+    }
+
+    pub(crate) fn get(&self, game_id: &str) -> Result<SharedGame> {
+        self.games.read().get(game_id).cloned().ok_or(Error::GameNotFound)
     }
 
-    pub(crate) fn get(&mut self, game_id: &str) -> Result<&mut Game> {
-        self.0.get_mut(game_id).ok_or(Error::GameNotFound)
+    /// Every game, releasing the map so the games can be locked individually
+    fn all(&self) -> Vec<(String, SharedGame)> {
+        self.games
+            .read()
+            .iter()
+            .map(|(game_id, game)| (game_id.clone(), Arc::clone(game)))
+            .collect()
     }
 
-    pub(crate) fn delete(&mut self, game_id: &str) {
-        self.0.remove(game_id);
+    /// Every game, the longest idle first
+    pub(crate) fn summaries(&self) -> Vec<GameSummary> {
+        let now = now();
+        let mut summaries = self
+            .all()
+            .into_iter()
+            .map(|(game_id, game)| game.lock().summary(game_id, now))
+            .collect::<Vec<_>>();
+        summaries.sort_by(|a, b| b.idle.cmp(&a.idle).then_with(|| a.game_id.cmp(&b.game_id)));
+        summaries
+    }
+
+    /// Export every game, each as of when it was reached
+    pub(crate) fn export_all(&self) -> Vec<GameExport> {
+        self.all()
+            .into_iter()
+            .map(|(game_id, game)| GameExport::new(&game_id, &game.lock()))
+            .collect()
+    }
+
+    /// Stop creating games and release every long-polling client
+    pub(crate) fn shut_down(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        for (_, game) in self.all() {
+            game.lock().notifier.close();
+        }
+    }
+
+    pub(crate) fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn delete(&self, game_id: &str) {
+        let game = self.games.write().remove(game_id);
+        if let Some(game) = game {
+            logging::event("game_deleted", &[]);
+            self.metrics.game_deleted(game_id);
+            game.lock().notifier.close();
+        }
     }
 }
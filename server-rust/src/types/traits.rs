@@ -1,12 +1,19 @@
-use crate::types::{BadRequest, Error, Guess};
+use crate::{
+    export::ExportFormat,
+    profiles::Window,
+    rate_limit::RateLimited,
+    types::{BadRequest, Error, Games, Guess, IfMatch, IfNoneMatch, Polled},
+};
+use rocket_contrib::json::Json;
 use rocket::{
-    http::{ContentType, Status},
-    request::Request,
-    response, Response,
+    http::{ContentType, RawStr, Status},
+    request::{self, FromFormValue, FromRequest, Request},
+    response, Outcome, Response, State,
 };
 use std::{
     hash::{Hash, Hasher},
     io::Cursor,
+    sync::Arc,
 };
 
 // TODO: This is terrible and should be removed but looks like HashSet does not impl Hash
@@ -26,13 +33,90 @@ impl Eq for Guess {}
 
 // Convert our custom Error type into HTTP responses
 impl<'r> response::Responder<'r> for Error {
-    fn respond_to(self, _: &Request) -> response::Result<'r> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        if let Outcome::Success(games) = request.guard::<State<Arc<Games>>>() {
+            games.metrics.error(&format!("{:?}", self));
+        }
+        let status = match self {
+            Self::VersionConflict => Status::Conflict,
+            Self::RateLimited => Status::TooManyRequests,
+            Self::ShuttingDown => Status::ServiceUnavailable,
+            Self::Unauthorized => Status::Unauthorized,
+            _ => Status::BadRequest,
+        };
         let body = BadRequest::new(self);
         let body = serde_json::to_string(&body).expect("to BadRequest serialize");
         Ok(Response::build()
-            .status(Status::BadRequest)
+            .status(status)
             .header(ContentType::JSON)
             .sized_body(Cursor::new(body))
             .finalize())
     }
 }
+
+impl<'v> FromFormValue<'v> for Window {
+    type Error = &'v RawStr;
+
+    fn from_form_value(value: &'v RawStr) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "all_time" => Ok(Self::AllTime),
+            "month" => Ok(Self::Month),
+            _ => Err(value),
+        }
+    }
+}
+
+impl<'v> FromFormValue<'v> for ExportFormat {
+    type Error = &'v RawStr;
+
+    fn from_form_value(value: &'v RawStr) -> Result<Self, Self::Error> {
+        value.as_str().parse().map_err(|_| value)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for IfMatch {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        Outcome::Success(Self(request.headers().get_one("If-Match").map(String::from)))
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for IfNoneMatch {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        Outcome::Success(Self(request.headers().get_one("If-None-Match").map(String::from)))
+    }
+}
+
+// Respond with the game and its ETag, or 304 Not Modified if the client has the current version
+impl<'r> response::Responder<'r> for Polled {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let mut response = match self.game {
+            Some(game) => Json(game).respond_to(request)?,
+            None => Response::build().status(Status::NotModified).finalize(),
+        };
+        response.set_raw_header("ETag", self.etag);
+        if let Outcome::Success(games) = request.guard::<State<Arc<Games>>>() {
+            if games.is_shutting_down() {
+                // Tell clients to expect the server to go away
+                response.set_raw_header("X-Shutting-Down", "true");
+            }
+            if let Some(notice) = &*games.notice.lock() {
+                // Tell clients there is a notice to fetch, changing whenever a new one is posted
+                response.set_raw_header("X-Notice", notice.posted_at.to_string());
+            }
+        }
+        Ok(response)
+    }
+}
+
+// Respond with 429 Too Many Requests and when to retry
+impl<'r> response::Responder<'r> for RateLimited {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let mut response = Error::RateLimited.respond_to(request)?;
+        response.set_raw_header("Retry-After", self.0.to_string());
+        Ok(response)
+    }
+}
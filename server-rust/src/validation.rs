@@ -0,0 +1,191 @@
+use crate::{
+    admin::{AnswerEdit, PlayerEdit},
+    bot::Bot,
+    export::GameExport,
+    matchmaking::QueueData,
+    profiles::ProfileData,
+    types::{check_length, Answer, CreateGameData, Error, NewGameData, PlayerData, Result},
+};
+use rocket::{
+    data::{self, Data, FromDataSimple},
+    http::Status,
+    Outcome, Request, State,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::io::{self, Read};
+
+/// The largest JSON body read when Rocket's `json` limit is not configured, matching `Json`
+const DEFAULT_JSON_LIMIT: u64 = 1 << 20;
+
+/// The longest text accepted from clients, in characters
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct InputLimits {
+    pub(crate) player_name: usize,
+    pub(crate) answer: usize,
+    pub(crate) game_id: usize,
+}
+
+impl Default for InputLimits {
+    fn default() -> Self {
+        Self {
+            player_name: 32,
+            answer: 64,
+            game_id: 32,
+        }
+    }
+}
+
+/// Characters which are invisible or reorder the text around them, so could disguise one name as
+/// another
+fn is_hidden(c: char) -> bool {
+    c.is_control()
+        || matches!(
+            c,
+            '\u{200b}'..='\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2060}'..='\u{2064}' | '\u{2066}'..='\u{2069}' | '\u{feff}'
+        )
+}
+
+impl InputLimits {
+    /// Player names must show at least one character and no hidden ones
+    pub(crate) fn player_name(&self, name: &str) -> Result<()> {
+        check_length(name, self.player_name)?;
+        if name.trim().is_empty() {
+            return Err(Error::InputEmpty);
+        }
+        if name.chars().any(is_hidden) {
+            return Err(Error::InvalidCharacters);
+        }
+        Ok(())
+    }
+
+    /// Answers may be empty but not contain hidden characters
+    pub(crate) fn answer(&self, answer: &str) -> Result<()> {
+        check_length(answer, self.answer)?;
+        if answer.chars().any(is_hidden) {
+            return Err(Error::InvalidCharacters);
+        }
+        Ok(())
+    }
+
+    /// Game ids appear in URLs so are limited to ASCII letters, digits, `-` and `_`
+    pub(crate) fn game_id(&self, game_id: &str) -> Result<()> {
+        check_length(game_id, self.game_id)?;
+        if game_id.is_empty() {
+            return Err(Error::InputEmpty);
+        }
+        if !game_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(Error::InvalidCharacters);
+        }
+        Ok(())
+    }
+}
+
+/// A request body whose text is checked against the `InputLimits`
+pub(crate) trait Validate {
+    fn check(&self, limits: &InputLimits) -> Result<()>;
+}
+
+impl Validate for PlayerData {
+    fn check(&self, limits: &InputLimits) -> Result<()> {
+        limits.player_name(&self.player)
+    }
+}
+
+impl Validate for CreateGameData {
+    fn check(&self, limits: &InputLimits) -> Result<()> {
+        limits.player_name(&self.player)
+    }
+}
+
+impl Validate for NewGameData {
+    fn check(&self, limits: &InputLimits) -> Result<()> {
+        if let Some(game_id) = &self.game_id {
+            limits.game_id(game_id)?;
+        }
+        self.game.check(limits)
+    }
+}
+
+impl Validate for Answer {
+    fn check(&self, limits: &InputLimits) -> Result<()> {
+        limits.player_name(&self.player)?;
+        limits.answer(&self.answer)
+    }
+}
+
+impl Validate for QueueData {
+    fn check(&self, limits: &InputLimits) -> Result<()> {
+        limits.player_name(&self.player)
+    }
+}
+
+impl Validate for Bot {
+    fn check(&self, limits: &InputLimits) -> Result<()> {
+        limits.player_name(&self.name)
+    }
+}
+
+impl Validate for ProfileData {
+    fn check(&self, limits: &InputLimits) -> Result<()> {
+        limits.player_name(&self.name)
+    }
+}
+
+impl Validate for AnswerEdit {
+    fn check(&self, limits: &InputLimits) -> Result<()> {
+        limits.player_name(&self.player)?;
+        match &self.answer {
+            Some(answer) => limits.answer(answer),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Validate for PlayerEdit {
+    fn check(&self, limits: &InputLimits) -> Result<()> {
+        limits.player_name(&self.player)?;
+        match &self.name {
+            Some(name) => limits.player_name(name),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Validate for GameExport {
+    fn check(&self, limits: &InputLimits) -> Result<()> {
+        self.validate(limits)
+    }
+}
+
+/// A JSON body like `Json`, holding the error if it breaks the `InputLimits`. Bodies which are not
+/// valid JSON for `T` are refused as `Json` refuses them.
+pub(crate) struct Valid<T>(Result<T>);
+
+impl<T> Valid<T> {
+    pub(crate) fn into_inner(self) -> Result<T> {
+        self.0
+    }
+}
+
+impl<T: DeserializeOwned + Validate> FromDataSimple for Valid<T> {
+    type Error = io::Error;
+
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, Self::Error> {
+        let size_limit = request.limits().get("json").unwrap_or(DEFAULT_JSON_LIMIT);
+        let mut body = String::with_capacity(512);
+        if let Err(e) = data.open().take(size_limit).read_to_string(&mut body) {
+            return Outcome::Failure((Status::BadRequest, e));
+        }
+        let value = match serde_json::from_str::<T>(&body) {
+            Ok(value) => value,
+            Err(e) if e.is_data() => return Outcome::Failure((Status::UnprocessableEntity, e.into())),
+            Err(e) => return Outcome::Failure((Status::BadRequest, e.into())),
+        };
+        let limits = match request.guard::<State<InputLimits>>() {
+            Outcome::Success(limits) => *limits,
+            _ => InputLimits::default(),
+        };
+        Outcome::Success(Self(value.check(&limits).map(|()| value)))
+    }
+}
@@ -0,0 +1,60 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+/// The words bots answer with unless a words file is given
+const BUNDLED_WORDS: &str = include_str!("../words.txt");
+
+/// Candidate answers for each category, used by bots
+#[derive(Default)]
+pub(crate) struct WordLookup {
+    words: HashMap<String, Vec<String>>,
+    all_words: Vec<String>,
+}
+
+fn normalize(category: &str) -> String {
+    category.trim().to_lowercase()
+}
+
+impl WordLookup {
+    /// The words shipped with the server in `words.txt`
+    pub(crate) fn bundled() -> Self {
+        let mut lookup = Self::default();
+        lookup.populate_from_str(BUNDLED_WORDS);
+        lookup
+    }
+
+    /// Populate from a file with one category per line in the form `Category: word, word, ...`
+    pub(crate) fn populate_from_file(&mut self, path: &Path) -> io::Result<()> {
+        self.populate_from_str(&fs::read_to_string(path)?);
+        Ok(())
+    }
+
+    fn populate_from_str(&mut self, text: &str) {
+        for line in text.lines() {
+            if let Some((category, words)) = line.split_once(':') {
+                self.insert(category, words.split(','));
+            }
+        }
+    }
+
+    pub(crate) fn insert<'a>(&mut self, category: &str, words: impl IntoIterator<Item = &'a str>) {
+        let words = words
+            .into_iter()
+            .map(|word| word.trim().to_lowercase())
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>();
+        self.all_words.extend(words.iter().cloned());
+        self.words.entry(normalize(category)).or_default().extend(words);
+    }
+
+    /// Whether bots have words for `category` rather than falling back to every word
+    pub(crate) fn has_category(&self, category: &str) -> bool {
+        self.words.contains_key(&normalize(category))
+    }
+
+    /// The words for a category, falling back to every known word for unknown categories
+    pub(crate) fn get(&self, category: &str) -> &[String] {
+        self.words
+            .get(&normalize(category))
+            .map_or(&self.all_words, |words| words)
+    }
+}